@@ -1,62 +1,312 @@
 use crate::{
     devices::{
         Device, Irq,
+        clint::{CLINT_END, CLINT_START, Clint},
         memory::{MEMORY_END, MEMORY_START, Memory},
         plic::{PLIC_END, PLIC_START, Plic},
-        uart::{UART_END, UART_START, Uart},
-        ysyx::{YSYX_END, YSYX_START, Ysyx, YsyxCommand},
+        uart::{self, UART_START, Uart, UartConfig},
+        ysyx::{YSYX_END, YSYX_START, Ysyx, YsyxCommand, YsyxConfig, YsyxEvent},
     },
     utils::{
         Exception, Size,
         channel::{Receiver, Sender},
+        serial,
     },
 };
+use std::{collections::VecDeque, io, ops::Range, thread::JoinHandle};
 
-#[derive(Debug)]
 pub struct Bus {
     memory: Memory,
     plic: Plic,
-    uart: Uart,
+    clint: Clint,
+    uarts: Vec<Uart>,
     ysyx: Ysyx,
+    // registered through `add_device`, by mapped range
+    devices: Vec<(Range<u32>, Box<dyn Device>)>,
 
+    divider: u64,
     count: u64,
+
+    tracer: Option<Tracer>,
+    log: VecDeque<Transaction>,
+    log_depth: usize,
+    // indexed like `device_map`
+    stats: Vec<DeviceStats>,
+}
+
+// memory, PLIC, CLINT and ysyx, ahead of the UARTs in `device_map`
+const BUILTIN_DEVICES: usize = 4;
+
+// a decoded address
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    Memory,
+    Plic,
+    Clint,
+    Ysyx,
+    Uart(usize),
+    Device(usize),
+}
+
+impl std::fmt::Debug for Bus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Bus")
+            .field("memory", &self.memory)
+            .field("plic", &self.plic)
+            .field("clint", &self.clint)
+            .field("uarts", &self.uarts)
+            .field("ysyx", &self.ysyx)
+            .field("devices", &self.devices)
+            .field("divider", &self.divider)
+            .field("count", &self.count)
+            .finish()
+    }
+}
+
+/// Accesses and clock ticks a device has serviced; see `Bus::stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DeviceStats {
+    pub reads: u64,
+    pub writes: u64,
+    pub clocks: u64,
+}
+
+pub type Tracer = Box<dyn FnMut(&Transaction)>;
+
+/// One `Bus::read` or `Bus::write`. `data` is the value written, or the
+/// value read (0 if the read faulted).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transaction {
+    pub address: u32,
+    pub size: Size,
+    pub write: bool,
+    pub data: u64,
+    pub result: Result<(), Exception>,
+}
+
+#[derive(Debug)]
+pub enum BusError {
+    // the new range intersects this already mapped device
+    Overlap { name: String, range: Range<u32> },
+    // a host resource, such as the ysyx disk image, couldn't be opened
+    Io(io::Error),
+}
+
+impl From<io::Error> for BusError {
+    fn from(error: io::Error) -> Self {
+        BusError::Io(error)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BusConfig {
+    /// Additional UARTs, next to the console UART at 0x10000000.
+    pub uarts: Vec<UartConfig>,
+    pub ysyx: YsyxConfig,
+}
+
+#[derive(Debug)]
+pub struct UartChannels {
+    pub sender: Sender<u8>,
+    pub receiver: Receiver<u8>,
 }
 
 #[derive(Debug)]
 pub struct DeviceController {
     pub uart_sender: Sender<u8>,
     pub uart_receiver: Receiver<u8>,
+    // one per `BusConfig::uarts` entry, in the same order
+    pub uarts: Vec<UartChannels>,
     pub ysyx_receiver: Receiver<YsyxCommand>,
+    // input delivered to the guest as if it came from the ysyx window
+    pub ysyx_events: Sender<YsyxEvent>,
+}
+
+impl DeviceController {
+    /// Feeds host stdin into the console UART from a background thread; see
+    /// `serial::forward_input` for how long the thread lives.
+    pub fn bridge_stdin(&self) -> JoinHandle<()> {
+        serial::forward_input(std::io::stdin(), self.uart_sender.clone())
+    }
+
+    /// Writes console UART output to host stdout from a background thread;
+    /// see `serial::forward_output`.
+    pub fn bridge_stdout(&self) -> JoinHandle<()> {
+        serial::forward_output(self.uart_receiver.clone(), std::io::stdout())
+    }
+
+    /// Serves the console UART over TCP; see `serial::serve_tcp`.
+    #[cfg(feature = "tcp")]
+    pub fn bridge_tcp(&self, listener: std::net::TcpListener) -> JoinHandle<()> {
+        serial::serve_tcp(
+            listener,
+            self.uart_sender.clone(),
+            self.uart_receiver.clone(),
+        )
+    }
+
+    /// Attaches the console UART to a new pseudo-terminal and prints its
+    /// path; see `serial::serve_pty`.
+    #[cfg(all(unix, feature = "pty"))]
+    pub fn bridge_pty(&self) -> std::io::Result<std::path::PathBuf> {
+        let path = serial::serve_pty(self.uart_sender.clone(), self.uart_receiver.clone())?;
+        println!("uart: {}", path.display());
+        Ok(path)
+    }
 }
 
 impl Bus {
-    pub fn new() -> (Bus, DeviceController) {
-        let (uart, uart_sender, uart_receiver) = Uart::new();
-        let (ysyx, ysyx_receiver) = Ysyx::new();
-        (
-            Bus {
-                memory: Memory::new(),
-                plic: Plic::new(),
-                uart,
-                ysyx,
-                count: 0,
-            },
+    pub fn new() -> Result<(Bus, DeviceController), BusError> {
+        Bus::with_config(BusConfig::default())
+    }
+
+    /// Fails if a configured UART overlaps another device, like
+    /// `add_device` does, or if the ysyx disk image or window can't be opened.
+    pub fn with_config(config: BusConfig) -> Result<(Bus, DeviceController), BusError> {
+        let (uart, uart_sender, uart_receiver) =
+            Uart::new(&UartConfig::new(UART_START, uart::INTERRUPT_ID));
+        let (ysyx, ysyx_receiver, ysyx_events) = Ysyx::new(&config.ysyx)?;
+        let mut bus = Bus {
+            memory: Memory::new(),
+            plic: Plic::new(),
+            clint: Clint::new(),
+            uarts: vec![uart],
+            ysyx,
+            devices: Vec::new(),
+            divider: 1001,
+            count: 0,
+            tracer: None,
+            log: VecDeque::new(),
+            log_depth: 0,
+            stats: vec![DeviceStats::default(); BUILTIN_DEVICES + 1],
+        };
+        let mut uart_channels = Vec::new();
+        for uart_config in &config.uarts {
+            let (uart, sender, receiver) = Uart::new(uart_config);
+            bus.check_overlap(&uart.address_range())?;
+            bus.uarts.push(uart);
+            bus.stats.push(DeviceStats::default());
+            uart_channels.push(UartChannels { sender, receiver });
+        }
+        Ok((
+            bus,
             DeviceController {
                 uart_sender,
                 uart_receiver,
+                uarts: uart_channels,
                 ysyx_receiver,
+                ysyx_events,
             },
-        )
+        ))
+    }
+
+    /// Maps `device` at its `address_range`, which must not overlap any
+    /// mapped device.
+    pub fn add_device(&mut self, device: Box<dyn Device>) -> Result<(), BusError> {
+        let range = device.address_range();
+        self.check_overlap(&range)?;
+        self.devices.push((range, device));
+        self.stats.push(DeviceStats::default());
+        Ok(())
+    }
+
+    fn check_overlap(&self, range: &Range<u32>) -> Result<(), BusError> {
+        match self
+            .device_map()
+            .into_iter()
+            .find(|(_, mapped)| range.start < mapped.end && mapped.start < range.end)
+        {
+            Some((name, mapped)) => Err(BusError::Overlap {
+                name,
+                range: mapped,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn decode(&self, address: u32) -> Option<Slot> {
+        match address {
+            MEMORY_START..=MEMORY_END => return Some(Slot::Memory),
+            PLIC_START..=PLIC_END => return Some(Slot::Plic),
+            CLINT_START..=CLINT_END => return Some(Slot::Clint),
+            YSYX_START..=YSYX_END => return Some(Slot::Ysyx),
+            _ => {}
+        }
+        if let Some(index) = self
+            .uarts
+            .iter()
+            .position(|uart| uart.address_range().contains(&address))
+        {
+            return Some(Slot::Uart(index));
+        }
+        if let Some(index) = self
+            .devices
+            .iter()
+            .position(|(range, _)| range.contains(&address))
+        {
+            return Some(Slot::Device(index));
+        }
+        None
+    }
+
+    fn device(&mut self, slot: Slot) -> &mut dyn Device {
+        match slot {
+            Slot::Memory => &mut self.memory,
+            Slot::Plic => &mut self.plic,
+            Slot::Clint => &mut self.clint,
+            Slot::Ysyx => &mut self.ysyx,
+            Slot::Uart(index) => &mut self.uarts[index],
+            Slot::Device(index) => self.devices[index].1.as_mut(),
+        }
+    }
+
+    // the slot's position in `device_map` and `stats`
+    fn index(&self, slot: Slot) -> usize {
+        match slot {
+            Slot::Memory => 0,
+            Slot::Plic => 1,
+            Slot::Clint => 2,
+            Slot::Ysyx => 3,
+            Slot::Uart(index) => BUILTIN_DEVICES + index,
+            Slot::Device(index) => BUILTIN_DEVICES + self.uarts.len() + index,
+        }
+    }
+
+    // bus-mastering devices run their queued transfers after each write
+    fn dma(&mut self, slot: Slot) {
+        match slot {
+            Slot::Ysyx => self.ysyx.dma(&mut self.memory),
+            Slot::Device(index) => self.devices[index].1.dma(&mut self.memory),
+            _ => {}
+        }
+    }
+
+    /// Device clocks run on one `clk` call in every `divider + 1`; the
+    /// default is 1001.
+    pub fn set_clock_divider(&mut self, divider: u64) {
+        self.divider = divider;
+        self.count = 0;
     }
 
     pub fn clk(&mut self) {
-        if self.count > 1000 {
+        if self.count >= self.divider {
             self.count = 0;
             let mut irq = Irq::new();
             self.memory.clk(&mut irq);
             self.plic.clk(&mut irq);
-            self.uart.clk(&mut irq);
+            self.clint.clk(&mut irq);
+            for uart in &mut self.uarts {
+                uart.clk(&mut irq);
+            }
             self.ysyx.clk(&mut irq);
+            for (_, device) in &mut self.devices {
+                // lets devices fed from the host side master the bus too
+                device.dma(&mut self.memory);
+                device.clk(&mut irq);
+            }
+            for stats in &mut self.stats {
+                stats.clocks += 1;
+            }
             for (irq, enable) in irq {
                 self.plic.irq(irq, enable);
             }
@@ -65,27 +315,484 @@ impl Bus {
         }
     }
 
+    /// Calls `tracer` after every `read`, `fetch` and `write`; `None` removes
+    /// it. 128-bit accesses are not traced.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(
+        &mut self,
+        address: u32,
+        size: Size,
+        write: bool,
+        data: u64,
+        result: Result<(), Exception>,
+    ) {
+        let transaction = Transaction {
+            address,
+            size,
+            write,
+            data,
+            result,
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer(&transaction);
+        }
+        if self.log_depth > 0 {
+            if self.log.len() == self.log_depth {
+                self.log.pop_front();
+            }
+            self.log.push_back(transaction);
+        }
+    }
+
+    /// Keeps the last `depth` transactions for `recent_transactions`; 0, the
+    /// default, turns the log off. Like the tracer, it skips 128-bit accesses.
+    pub fn set_transaction_log(&mut self, depth: usize) {
+        self.log_depth = depth;
+        let excess = self.log.len().saturating_sub(depth);
+        self.log.drain(..excess);
+        self.log.shrink_to(depth);
+    }
+
+    /// The logged transactions, oldest first.
+    pub fn recent_transactions(&self) -> Vec<Transaction> {
+        self.log.iter().copied().collect()
+    }
+
+    /// Returns every device to its power-on state, clearing RAM, without
+    /// tearing down the host side: channels, bridges and the ysyx window
+    /// stay connected.
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.plic.reset();
+        self.clint.reset();
+        for uart in &mut self.uarts {
+            uart.reset();
+        }
+        self.ysyx.reset();
+        for (_, device) in &mut self.devices {
+            device.reset();
+        }
+        self.count = 0;
+    }
+
     pub fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
-        match address {
-            MEMORY_START..=MEMORY_END => self.memory.read(address, size),
-            PLIC_START..=PLIC_END => self.plic.read(address, size),
-            UART_START..=UART_END => self.uart.read(address, size),
-            YSYX_START..=YSYX_END => self.ysyx.read(address, size),
-            _ => Err(Exception::BusException),
+        let result = self.read_device(address, size);
+        if self.tracer.is_some() || self.log_depth > 0 {
+            let data = *result.as_ref().unwrap_or(&0);
+            self.trace(address, size, false, data, result.map(|_| ()));
         }
+        result
+    }
+
+    fn read_device(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        let slot = self
+            .decode(address)
+            .ok_or(Exception::LoadAccessFault { address, size })?;
+        let index = self.index(slot);
+        self.stats[index].reads += 1;
+        self.device(slot).read(address, size)
+    }
+
+    /// Reads an instruction word, reporting faults as instruction faults
+    /// rather than the data-load variants `read` would return.
+    pub fn fetch(&mut self, address: u32) -> Result<u32, Exception> {
+        let size = Size::_4;
+        self.read(address, size)
+            .map(|data| data as u32)
+            .map_err(|exception| match exception {
+                Exception::LoadAddressMisaligned { address, size } => {
+                    Exception::InstructionAddressMisaligned { address, size }
+                }
+                Exception::LoadAccessFault { address, size } => {
+                    Exception::InstructionAccessFault { address, size }
+                }
+                _ => Exception::InstructionAccessFault { address, size },
+            })
     }
 
     pub fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
-        match address {
-            MEMORY_START..=MEMORY_END => self.memory.write(address, size, data),
-            PLIC_START..=PLIC_END => self.plic.write(address, size, data),
-            UART_START..=UART_END => self.uart.write(address, size, data),
-            YSYX_START..=YSYX_END => self.ysyx.write(address, size, data),
-            _ => Err(Exception::BusException),
+        let result = self.write_device(address, size, data);
+        if self.tracer.is_some() || self.log_depth > 0 {
+            self.trace(address, size, true, data, result);
         }
+        result
+    }
+
+    fn write_device(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        let slot = self
+            .decode(address)
+            .ok_or(Exception::StoreAccessFault { address, size })?;
+        let index = self.index(slot);
+        self.stats[index].writes += 1;
+        self.device(slot).write(address, size, data)?;
+        self.dma(slot);
+        Ok(())
+    }
+
+    pub fn read_u128(&mut self, address: u32) -> Result<u128, Exception> {
+        let slot = self.decode(address).ok_or(Exception::LoadAccessFault {
+            address,
+            size: Size::_16,
+        })?;
+        let index = self.index(slot);
+        self.stats[index].reads += 1;
+        self.device(slot).read_u128(address)
+    }
+
+    pub fn write_u128(&mut self, address: u32, data: u128) -> Result<(), Exception> {
+        let slot = self.decode(address).ok_or(Exception::StoreAccessFault {
+            address,
+            size: Size::_16,
+        })?;
+        let index = self.index(slot);
+        self.stats[index].writes += 1;
+        self.device(slot).write_u128(address, data)
+    }
+
+    /// Every mapped device's name and address range, built-in devices first.
+    pub fn device_map(&self) -> Vec<(String, Range<u32>)> {
+        let builtin: [&dyn Device; BUILTIN_DEVICES] =
+            [&self.memory, &self.plic, &self.clint, &self.ysyx];
+        builtin
+            .into_iter()
+            .chain(self.uarts.iter().map(|uart| uart as &dyn Device))
+            .map(|device| (device.name().to_string(), device.address_range()))
+            .chain(
+                self.devices
+                    .iter()
+                    .map(|(range, device)| (device.name().to_string(), range.clone())),
+            )
+            .collect()
+    }
+
+    /// Per-device access and clock counts since the bus was created, in
+    /// `device_map` order.
+    pub fn stats(&self) -> Vec<(String, DeviceStats)> {
+        self.device_map()
+            .into_iter()
+            .zip(&self.stats)
+            .map(|((name, _), stats)| (name, *stats))
+            .collect()
     }
 
     pub fn interrupt(&mut self) -> Option<bool> {
         self.plic.check_interrupt()
     }
+
+    pub fn plic(&mut self) -> &mut Plic {
+        &mut self.plic
+    }
+
+    /// The CLINT's machine timer interrupt (MTIP) level.
+    pub fn timer_interrupt(&self) -> bool {
+        self.clint.timer_interrupt()
+    }
+
+    /// The CLINT's machine software interrupt (MSIP) level.
+    pub fn software_interrupt(&self) -> bool {
+        self.clint.software_interrupt()
+    }
+
+    pub fn clint(&mut self) -> &mut Clint {
+        &mut self.clint
+    }
+
+    pub fn ysyx(&mut self) -> &mut Ysyx {
+        &mut self.ysyx
+    }
+
+    /// The UART at `index`, 0 being the console UART and the rest following
+    /// `BusConfig::uarts`.
+    pub fn uart(&mut self, index: usize) -> Option<&mut Uart> {
+        self.uarts.get_mut(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no window, so the tests run without a display
+    fn headless(uarts: Vec<UartConfig>) -> Result<(Bus, DeviceController), BusError> {
+        Bus::with_config(BusConfig {
+            uarts,
+            ysyx: YsyxConfig {
+                headless: true,
+                ..Default::default()
+            },
+        })
+    }
+
+    #[test]
+    fn fetch_faults_are_instruction_faults() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        assert_eq!(
+            bus.fetch(0x1000),
+            Err(Exception::InstructionAccessFault {
+                address: 0x1000,
+                size: Size::_4,
+            })
+        );
+        assert_eq!(
+            bus.fetch(MEMORY_START + 2),
+            Err(Exception::InstructionAddressMisaligned {
+                address: MEMORY_START + 2,
+                size: Size::_4,
+            })
+        );
+        bus.write(MEMORY_START, Size::_4, 0x0000_0013).unwrap();
+        assert_eq!(bus.fetch(MEMORY_START), Ok(0x0000_0013));
+    }
+
+    #[test]
+    fn uarts_route_to_their_own_channels() {
+        let (mut bus, controller) = headless(vec![
+            UartConfig::new(0x1000_1000, 10),
+            UartConfig::new(0x1000_2000, 11),
+        ])
+        .unwrap();
+        bus.write(0x1000_2000, Size::_1, b'b' as u64).unwrap();
+        bus.write(0x1000_1000, Size::_1, b'a' as u64).unwrap();
+        for index in 1..=2 {
+            bus.uart(index).unwrap().flush();
+        }
+        assert_eq!(controller.uarts[0].receiver.recv(), Some(b'a'));
+        assert_eq!(controller.uarts[1].receiver.recv(), Some(b'b'));
+        assert!(!controller.uarts[0].receiver.avaliable());
+        assert!(!controller.uart_receiver.avaliable());
+    }
+
+    #[test]
+    fn overlapping_uarts_are_rejected() {
+        let overlap = |base| headless(vec![UartConfig::new(base, 10)]).err();
+        assert!(matches!(
+            overlap(UART_START + 4),
+            Some(BusError::Overlap { .. })
+        ));
+        assert!(matches!(
+            overlap(MEMORY_START),
+            Some(BusError::Overlap { .. })
+        ));
+        assert!(matches!(
+            overlap(PLIC_START + 0x100),
+            Some(BusError::Overlap { .. })
+        ));
+        assert!(matches!(
+            overlap(CLINT_START),
+            Some(BusError::Overlap { .. })
+        ));
+        assert!(matches!(
+            overlap(YSYX_START),
+            Some(BusError::Overlap { .. })
+        ));
+        assert!(
+            headless(vec![
+                UartConfig::new(0x1000_1000, 10),
+                UartConfig::new(0x1000_1004, 11),
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn missing_disk_image_is_an_error() {
+        let result = Bus::with_config(BusConfig {
+            ysyx: YsyxConfig {
+                disk: Some("/nonexistent/disk.img".into()),
+                headless: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(BusError::Io(_))));
+    }
+
+    #[test]
+    fn added_devices_are_reachable_at_their_range() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        let rom = crate::devices::rom::Rom::new(0x3000_0000, vec![0x78, 0x56, 0x34, 0x12]);
+        bus.add_device(Box::new(rom)).unwrap();
+        assert_eq!(bus.read(0x3000_0000, Size::_4), Ok(0x1234_5678));
+        let clash = crate::devices::rom::Rom::new(0x3000_0000, vec![0; 4]);
+        assert!(matches!(
+            bus.add_device(Box::new(clash)),
+            Err(BusError::Overlap { .. })
+        ));
+    }
+
+    #[test]
+    fn devices_clock_once_every_divider_plus_one_calls() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        let clocks = |bus: &Bus| bus.stats()[0].1.clocks;
+        for _ in 0..1001 {
+            bus.clk();
+        }
+        assert_eq!(clocks(&bus), 0);
+        bus.clk();
+        assert_eq!(clocks(&bus), 1);
+        bus.set_clock_divider(2);
+        for _ in 0..9 {
+            bus.clk();
+        }
+        assert_eq!(clocks(&bus), 4);
+    }
+
+    #[test]
+    fn tracer_sees_every_access() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let log = seen.clone();
+        bus.set_tracer(Some(Box::new(move |transaction| {
+            log.borrow_mut().push(*transaction)
+        })));
+        bus.write(MEMORY_START, Size::_4, 0xdead_beef).unwrap();
+        bus.read(MEMORY_START, Size::_2).unwrap();
+        let _ = bus.read(0x1000, Size::_1);
+        let fault = Exception::LoadAccessFault {
+            address: 0x1000,
+            size: Size::_1,
+        };
+        assert_eq!(
+            *seen.borrow(),
+            [
+                Transaction {
+                    address: MEMORY_START,
+                    size: Size::_4,
+                    write: true,
+                    data: 0xdead_beef,
+                    result: Ok(()),
+                },
+                Transaction {
+                    address: MEMORY_START,
+                    size: Size::_2,
+                    write: false,
+                    data: 0xbeef,
+                    result: Ok(()),
+                },
+                Transaction {
+                    address: 0x1000,
+                    size: Size::_1,
+                    write: false,
+                    data: 0,
+                    result: Err(fault),
+                },
+            ]
+        );
+        bus.set_tracer(None);
+        bus.read(MEMORY_START, Size::_4).unwrap();
+        assert_eq!(seen.borrow().len(), 3);
+    }
+
+    #[test]
+    fn reset_returns_devices_to_power_on() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        bus.set_clock_divider(0);
+        bus.write(MEMORY_START, Size::_4, 0x1234_5678).unwrap();
+        // THR empty interrupt
+        bus.write(UART_START + 1, Size::_1, 0x02).unwrap();
+        bus.clk();
+        assert!(bus.plic().is_pending(uart::INTERRUPT_ID));
+        bus.reset();
+        assert_eq!(bus.read(MEMORY_START, Size::_4), Ok(0));
+        assert!(!bus.plic().is_pending(uart::INTERRUPT_ID));
+        assert_eq!(bus.read(UART_START + 1, Size::_1), Ok(0));
+        bus.clk();
+        assert!(!bus.plic().is_pending(uart::INTERRUPT_ID));
+    }
+
+    #[test]
+    fn device_map_lists_builtin_ranges() {
+        let (bus, _controller) = headless(vec![UartConfig::new(0x1000_1000, 10)]).unwrap();
+        let map = bus.device_map();
+        let starts = |name: &str| {
+            map.iter()
+                .filter(|(mapped, _)| mapped == name)
+                .map(|(_, range)| range.start)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(starts("memory"), [MEMORY_START]);
+        assert_eq!(starts("plic"), [PLIC_START]);
+        assert_eq!(starts("clint"), [CLINT_START]);
+        assert_eq!(starts("ysyx"), [YSYX_START]);
+        assert_eq!(starts("uart"), [UART_START, 0x1000_1000]);
+        assert!(map.contains(&("memory".to_string(), MEMORY_START..MEMORY_END + 1)));
+        assert!(map.contains(&("plic".to_string(), PLIC_START..PLIC_END + 1)));
+    }
+
+    #[test]
+    fn devices_overlapping_the_uart_are_rejected() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        let rom = crate::devices::rom::Rom::new(UART_START + 4, vec![0; 8]);
+        match bus.add_device(Box::new(rom)) {
+            Err(BusError::Overlap { name, range }) => {
+                assert_eq!(name, "uart");
+                assert_eq!(range.start, UART_START);
+            }
+            result => panic!("expected an overlap, got {result:?}"),
+        }
+        assert_eq!(bus.device_map().len(), BUILTIN_DEVICES + 1);
+    }
+
+    #[test]
+    fn unmapped_accesses_report_the_address() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        assert_eq!(
+            bus.read(0x2000_0123, Size::_2),
+            Err(Exception::LoadAccessFault {
+                address: 0x2000_0123,
+                size: Size::_2,
+            })
+        );
+        assert_eq!(
+            bus.write(0x7fff_fffc, Size::_8, 0),
+            Err(Exception::StoreAccessFault {
+                address: 0x7fff_fffc,
+                size: Size::_8,
+            })
+        );
+    }
+
+    #[test]
+    fn stats_count_accesses_per_device() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        bus.set_clock_divider(0);
+        for offset in 0..3 {
+            bus.write(MEMORY_START + offset * 4, Size::_4, offset as u64)
+                .unwrap();
+        }
+        bus.read(MEMORY_START, Size::_4).unwrap();
+        bus.read(UART_START + 5, Size::_1).unwrap();
+        bus.clk();
+        bus.clk();
+        let stats = bus.stats();
+        let count = |name: &str| stats.iter().find(|(mapped, _)| mapped == name).unwrap().1;
+        let memory = count("memory");
+        assert_eq!((memory.reads, memory.writes, memory.clocks), (1, 3, 2));
+        let uart = count("uart");
+        assert_eq!((uart.reads, uart.writes, uart.clocks), (1, 0, 2));
+        assert_eq!(count("plic").reads, 0);
+    }
+
+    #[test]
+    fn transaction_log_keeps_the_most_recent() {
+        let (mut bus, _controller) = headless(Vec::new()).unwrap();
+        bus.write(MEMORY_START, Size::_4, 1).unwrap();
+        assert!(bus.recent_transactions().is_empty());
+        bus.set_transaction_log(3);
+        for value in 0..5 {
+            bus.write(MEMORY_START, Size::_4, value).unwrap();
+        }
+        let data: Vec<u64> = bus
+            .recent_transactions()
+            .iter()
+            .map(|transaction| transaction.data)
+            .collect();
+        assert_eq!(data, [2, 3, 4]);
+        bus.set_transaction_log(1);
+        assert_eq!(bus.recent_transactions()[0].data, 4);
+        assert_eq!(bus.recent_transactions().len(), 1);
+    }
 }
@@ -0,0 +1,130 @@
+use std::ops::Range;
+
+use crate::utils::Exception;
+
+use super::{Device, Irq, Size};
+
+pub(crate) const CLINT_START: u32 = 0x02000000;
+pub(crate) const CLINT_END: u32 = CLINT_START + 0xFFFF;
+
+const CLINT_MSIP: u32 = CLINT_START;
+const CLINT_MTIMECMP: u32 = CLINT_START + 0x4000;
+const CLINT_MTIME: u32 = CLINT_START + 0xBFF8;
+
+// core-local interruptor for a single hart; its interrupts go straight to
+// the hart rather than through the PLIC
+#[derive(Debug)]
+pub struct Clint {
+    msip: bool,
+    mtimecmp: u64,
+    mtime: u64,
+}
+
+// the 64-bit timer registers take either one doubleword access or word
+// accesses to either half
+fn half(register: u32, address: u32, size: Size) -> Option<u32> {
+    match (size, address.checked_sub(register)?) {
+        (Size::_8, 0) => Some(0),
+        (Size::_4, offset @ (0 | 4)) => Some(offset * 8),
+        _ => None,
+    }
+}
+
+fn merge(old: u64, shift: u32, size: Size, data: u64) -> u64 {
+    let mask = (size.mask() as u64) << shift;
+    (old & !mask) | ((data << shift) & mask)
+}
+
+impl Clint {
+    pub(crate) fn new() -> Clint {
+        Clint {
+            msip: false,
+            // no timer interrupt until software programs a deadline
+            mtimecmp: u64::MAX,
+            mtime: 0,
+        }
+    }
+
+    /// MTIP: `mtime` has reached `mtimecmp`.
+    pub fn timer_interrupt(&self) -> bool {
+        self.mtime >= self.mtimecmp
+    }
+
+    /// MSIP, as last written by software.
+    pub fn software_interrupt(&self) -> bool {
+        self.msip
+    }
+
+    pub fn mtime(&self) -> u64 {
+        self.mtime
+    }
+}
+
+impl Device for Clint {
+    fn name(&self) -> &str {
+        "clint"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        CLINT_START..CLINT_END + 1
+    }
+
+    // one tick per device clock
+    fn clk(&mut self, _irq: &mut Irq) {
+        self.mtime = self.mtime.wrapping_add(1);
+    }
+
+    fn reset(&mut self) {
+        *self = Clint::new();
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if address == CLINT_MSIP && size == Size::_4 {
+            return Ok(self.msip as u64);
+        }
+        if let Some(shift) = half(CLINT_MTIMECMP, address, size) {
+            return Ok((self.mtimecmp >> shift) & size.mask() as u64);
+        }
+        if let Some(shift) = half(CLINT_MTIME, address, size) {
+            return Ok((self.mtime >> shift) & size.mask() as u64);
+        }
+        Err(Exception::LoadAccessFault { address, size })
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if address == CLINT_MSIP && size == Size::_4 {
+            self.msip = data & 1 != 0;
+        } else if let Some(shift) = half(CLINT_MTIMECMP, address, size) {
+            self.mtimecmp = merge(self.mtimecmp, shift, size, data);
+        } else if let Some(shift) = half(CLINT_MTIME, address, size) {
+            self.mtime = merge(self.mtime, shift, size, data);
+        } else {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mtimecmp_below_mtime_raises_the_timer_interrupt() {
+        let mut clint = Clint::new();
+        let mut irq = Irq::new();
+        for _ in 0..10 {
+            clint.clk(&mut irq);
+        }
+        assert!(!clint.timer_interrupt());
+        clint.write(CLINT_MTIMECMP, Size::_8, 5).unwrap();
+        assert!(clint.timer_interrupt());
+        clint.write(CLINT_MTIMECMP, Size::_4, 11).unwrap();
+        clint.write(CLINT_MTIMECMP + 4, Size::_4, 0).unwrap();
+        assert!(!clint.timer_interrupt());
+        clint.clk(&mut irq);
+        assert!(clint.timer_interrupt());
+        assert_eq!(clint.read(CLINT_MTIME, Size::_8), Ok(11));
+        assert!(irq.next().is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use std::ops::Range;
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, DmaPort, Irq, memory::in_ram};
+
+pub(crate) const DMA_SIZE: u32 = 0x100;
+
+const DMA_SRC: u32 = 0x00;
+const DMA_DST: u32 = 0x04;
+// bytes to copy
+const DMA_LEN: u32 = 0x08;
+const DMA_CTRL: u32 = 0x0c;
+// DONE and ERROR are write 1 to clear
+const DMA_STATUS: u32 = 0x10;
+
+// starts a transfer, reads back 0
+const DMA_CTRL_START: u32 = 0b01;
+// interrupt while DONE is set
+const DMA_CTRL_IE: u32 = 0b10;
+
+const DMA_STATUS_BUSY: u32 = 0b001;
+const DMA_STATUS_DONE: u32 = 0b010;
+// part of the source or destination wasn't RAM; DONE is set as well
+const DMA_STATUS_ERROR: u32 = 0b100;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DmaConfig {
+    pub base: u32,
+    pub interrupt_id: u32,
+}
+
+impl DmaConfig {
+    pub fn new(base: u32, interrupt_id: u32) -> DmaConfig {
+        DmaConfig { base, interrupt_id }
+    }
+}
+
+/// A single-channel DMA controller copying blocks within RAM. A transfer runs
+/// as soon as it is started, before the guest's next access.
+#[derive(Debug)]
+pub struct Dma {
+    base: u32,
+    interrupt_id: u32,
+    src: u32,
+    dst: u32,
+    len: u32,
+    ctrl: u32,
+    status: u32,
+}
+
+impl Dma {
+    pub fn new(config: &DmaConfig) -> Dma {
+        Dma {
+            base: config.base,
+            interrupt_id: config.interrupt_id,
+            src: 0,
+            dst: 0,
+            len: 0,
+            ctrl: 0,
+            status: 0,
+        }
+    }
+
+    // overlapping ranges copy as if through a temporary buffer, which is
+    // only allocated once both ranges are known to be RAM
+    fn copy(&self, port: &mut dyn DmaPort) -> Result<(), Exception> {
+        if !in_ram(self.src, self.len as u64) {
+            return Err(Exception::LoadAccessFault {
+                address: self.src,
+                size: Size::_1,
+            });
+        }
+        if !in_ram(self.dst, self.len as u64) {
+            return Err(Exception::StoreAccessFault {
+                address: self.dst,
+                size: Size::_1,
+            });
+        }
+        let mut data = vec![0; self.len as usize];
+        port.dma_read(self.src, &mut data)?;
+        port.dma_write(self.dst, &data)
+    }
+}
+
+impl Device for Dma {
+    fn name(&self) -> &str {
+        "dma"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + DMA_SIZE
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        irq.irq(
+            self.interrupt_id,
+            self.ctrl & DMA_CTRL_IE != 0 && self.status & DMA_STATUS_DONE != 0,
+        );
+    }
+
+    fn reset(&mut self) {
+        self.src = 0;
+        self.dst = 0;
+        self.len = 0;
+        self.ctrl = 0;
+        self.status = 0;
+    }
+
+    fn dma(&mut self, port: &mut dyn DmaPort) {
+        if self.status & DMA_STATUS_BUSY == 0 {
+            return;
+        }
+        self.status = match self.copy(port) {
+            Ok(()) => DMA_STATUS_DONE,
+            Err(_) => DMA_STATUS_DONE | DMA_STATUS_ERROR,
+        };
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            DMA_SRC => self.src,
+            DMA_DST => self.dst,
+            DMA_LEN => self.len,
+            DMA_CTRL => self.ctrl,
+            DMA_STATUS => self.status,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            DMA_SRC => self.src = data,
+            DMA_DST => self.dst = data,
+            DMA_LEN => self.len = data,
+            DMA_CTRL => {
+                if data & DMA_CTRL_START != 0 && self.status & DMA_STATUS_BUSY == 0 {
+                    self.status = DMA_STATUS_BUSY;
+                }
+                self.ctrl = data & !DMA_CTRL_START;
+            }
+            DMA_STATUS => self.status &= !(data & (DMA_STATUS_DONE | DMA_STATUS_ERROR)),
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::memory::{MEMORY_START, Memory};
+
+    const BASE: u32 = 0x1000_c000;
+
+    fn start(dma: &mut Dma, memory: &mut Memory, src: u32, dst: u32, len: u32) -> u64 {
+        for (register, value) in [
+            (DMA_SRC, src),
+            (DMA_DST, dst),
+            (DMA_LEN, len),
+            (DMA_CTRL, DMA_CTRL_START | DMA_CTRL_IE),
+        ] {
+            dma.write(BASE + register, Size::_4, value as u64).unwrap();
+        }
+        dma.dma(memory);
+        dma.read(BASE + DMA_STATUS, Size::_4).unwrap()
+    }
+
+    #[test]
+    fn transfer_copies_ram_and_interrupts() {
+        let mut dma = Dma::new(&DmaConfig::new(BASE, 7));
+        let mut memory = Memory::new();
+        let data: Vec<u8> = (0..100).collect();
+        memory.dma_write(MEMORY_START, &data).unwrap();
+        let status = start(
+            &mut dma,
+            &mut memory,
+            MEMORY_START,
+            MEMORY_START + 0x1000,
+            100,
+        );
+        assert_eq!(status, DMA_STATUS_DONE as u64);
+        let mut copied = vec![0; 100];
+        memory.dma_read(MEMORY_START + 0x1000, &mut copied).unwrap();
+        assert_eq!(copied, data);
+
+        let mut irq = Irq::new();
+        dma.clk(&mut irq);
+        assert_eq!(irq.next(), Some((7, true)));
+        dma.write(BASE + DMA_STATUS, Size::_4, DMA_STATUS_DONE as u64)
+            .unwrap();
+        dma.clk(&mut irq);
+        assert_eq!(irq.next(), Some((7, false)));
+    }
+
+    #[test]
+    fn transfers_outside_ram_fail_up_front() {
+        let mut dma = Dma::new(&DmaConfig::new(BASE, 7));
+        let mut memory = Memory::new();
+        let error = (DMA_STATUS_DONE | DMA_STATUS_ERROR) as u64;
+        assert_eq!(
+            start(&mut dma, &mut memory, MEMORY_START, 0x1000, 16),
+            error
+        );
+        dma.write(BASE + DMA_STATUS, Size::_4, error).unwrap();
+        assert_eq!(
+            start(
+                &mut dma,
+                &mut memory,
+                MEMORY_START,
+                MEMORY_START + 4,
+                u32::MAX
+            ),
+            error
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::PathBuf,
+};
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+// Intel-style command set, written as the low byte of any store
+const CMD_READ_ARRAY: u8 = 0xFF;
+const CMD_READ_STATUS: u8 = 0x70;
+const CMD_CLEAR_STATUS: u8 = 0x50;
+const CMD_PROGRAM: u8 = 0x40;
+const CMD_PROGRAM_ALT: u8 = 0x10;
+const CMD_ERASE_SETUP: u8 = 0x20;
+const CMD_ERASE_CONFIRM: u8 = 0xD0;
+
+// operations complete immediately, so the device is always ready; the error
+// bits stay set until CLEAR_STATUS
+const STATUS_READY: u8 = 0x80;
+const STATUS_ERASE_ERROR: u8 = 0x20;
+const STATUS_PROGRAM_ERROR: u8 = 0x10;
+
+const ERASED: u8 = 0xFF;
+
+#[derive(Debug, Clone)]
+pub struct FlashConfig {
+    pub base: u32,
+    /// Backing image, used in place; its size is rounded down to whole
+    /// sectors.
+    pub image: PathBuf,
+    /// Erase granularity in bytes, a power of two.
+    pub sector_size: u32,
+}
+
+impl FlashConfig {
+    pub fn new(base: u32, image: impl Into<PathBuf>) -> FlashConfig {
+        FlashConfig {
+            base,
+            image: image.into(),
+            sector_size: 64 * 1024,
+        }
+    }
+}
+
+// what the next access means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    ReadArray,
+    // reads return the status register
+    Status,
+    // the next store is programmed
+    Program,
+    // the next store must be ERASE_CONFIRM in the sector to erase
+    EraseSetup,
+}
+
+/// NOR flash with a CFI-style command interface: reads return the array until a
+/// command is written, programming can only clear bits, and a sector must be
+/// erased back to 0xFF before its bits can be set again. Changes are written
+/// through to the image.
+#[derive(Debug)]
+pub struct Flash {
+    base: u32,
+    sector_size: u32,
+    image: File,
+    data: Vec<u8>,
+    mode: Mode,
+    status: u8,
+}
+
+impl Flash {
+    pub fn new(config: &FlashConfig) -> io::Result<Flash> {
+        assert!(
+            config.sector_size.is_power_of_two(),
+            "flash sectors are a power of two"
+        );
+        let mut image = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&config.image)?;
+        let len = image.metadata()?.len() / config.sector_size as u64 * config.sector_size as u64;
+        if len > u32::MAX as u64 || config.base.checked_add(len as u32).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "flash image runs past the end of the address space",
+            ));
+        }
+        let mut data = vec![0; len as usize];
+        image.read_exact(&mut data)?;
+        Ok(Flash {
+            base: config.base,
+            sector_size: config.sector_size,
+            image,
+            data,
+            mode: Mode::ReadArray,
+            status: STATUS_READY,
+        })
+    }
+
+    fn command(&mut self, offset: usize, bytes: &[u8]) {
+        self.mode = match self.mode {
+            Mode::Program => {
+                if self.program(offset, bytes).is_none() {
+                    self.status |= STATUS_PROGRAM_ERROR;
+                }
+                Mode::Status
+            }
+            Mode::EraseSetup => {
+                if bytes[0] != CMD_ERASE_CONFIRM {
+                    // command sequence error
+                    self.status |= STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR;
+                } else if self.erase(offset).is_none() {
+                    self.status |= STATUS_ERASE_ERROR;
+                }
+                Mode::Status
+            }
+            mode => match bytes[0] {
+                CMD_READ_ARRAY => Mode::ReadArray,
+                CMD_READ_STATUS => Mode::Status,
+                CMD_CLEAR_STATUS => {
+                    self.status = STATUS_READY;
+                    mode
+                }
+                CMD_PROGRAM | CMD_PROGRAM_ALT => Mode::Program,
+                CMD_ERASE_SETUP => Mode::EraseSetup,
+                _ => mode,
+            },
+        };
+    }
+
+    // fails, leaving the array as it was, if any bit would go from 0 to 1
+    fn program(&mut self, offset: usize, bytes: &[u8]) -> Option<()> {
+        let old = &self.data[offset..offset + bytes.len()];
+        if old.iter().zip(bytes).any(|(old, new)| !old & new != 0) {
+            return None;
+        }
+        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.write_back(offset..offset + bytes.len())
+    }
+
+    fn erase(&mut self, offset: usize) -> Option<()> {
+        let start = offset & !(self.sector_size as usize - 1);
+        let sector = start..start + self.sector_size as usize;
+        self.data[sector.clone()].fill(ERASED);
+        self.write_back(sector)
+    }
+
+    fn write_back(&mut self, range: Range<usize>) -> Option<()> {
+        self.image.seek(SeekFrom::Start(range.start as u64)).ok()?;
+        self.image.write_all(&self.data[range]).ok()
+    }
+}
+
+impl Device for Flash {
+    fn name(&self) -> &str {
+        "flash"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + self.data.len() as u32
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {}
+
+    // the array is nonvolatile; only the command state goes back
+    fn reset(&mut self) {
+        self.mode = Mode::ReadArray;
+        self.status = STATUS_READY;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if !size.is_aligned(address) {
+            return Err(Exception::LoadAddressMisaligned { address, size });
+        }
+        let offset = (address - self.base) as usize;
+        let len = size.bytes();
+        match self.data.get(offset..offset + len) {
+            Some(_) if len <= 8 && self.mode != Mode::ReadArray => Ok(self.status as u64),
+            Some(bytes) if len <= 8 => {
+                let mut data = [0; 8];
+                data[..len].copy_from_slice(bytes);
+                Ok(u64::from_le_bytes(data))
+            }
+            _ => Err(Exception::LoadAccessFault { address, size }),
+        }
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if !size.is_aligned(address) {
+            return Err(Exception::StoreAddressMisaligned { address, size });
+        }
+        let offset = (address - self.base) as usize;
+        let len = size.bytes();
+        if len > 8 || offset + len > self.data.len() {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        self.command(offset, &data.to_le_bytes()[..len]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x2000_0000;
+
+    fn store(flash: &mut Flash, offset: u32, data: u8) {
+        flash.write(BASE + offset, Size::_1, data as u64).unwrap();
+    }
+
+    fn load(flash: &mut Flash, offset: u32) -> u8 {
+        flash.read(BASE + offset, Size::_1).unwrap() as u8
+    }
+
+    #[test]
+    fn bits_only_return_to_one_through_an_erase() {
+        let path = std::env::temp_dir().join(format!("flash-{}.img", std::process::id()));
+        std::fs::write(&path, [ERASED; 0x200]).unwrap();
+        let mut flash = Flash::new(&FlashConfig {
+            sector_size: 0x100,
+            ..FlashConfig::new(BASE, &path)
+        })
+        .unwrap();
+
+        store(&mut flash, 0x110, CMD_PROGRAM);
+        store(&mut flash, 0x110, 0x0f);
+        assert_eq!(load(&mut flash, 0x110), STATUS_READY);
+        store(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(load(&mut flash, 0x110), 0x0f);
+
+        // 0x0f to 0xf0 would set bits
+        store(&mut flash, 0x110, CMD_PROGRAM);
+        store(&mut flash, 0x110, 0xf0);
+        assert_eq!(load(&mut flash, 0x110), STATUS_READY | STATUS_PROGRAM_ERROR);
+        store(&mut flash, 0, CMD_CLEAR_STATUS);
+        store(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(load(&mut flash, 0x110), 0x0f);
+
+        store(&mut flash, 0x180, CMD_ERASE_SETUP);
+        store(&mut flash, 0x180, CMD_ERASE_CONFIRM);
+        assert_eq!(load(&mut flash, 0x110), STATUS_READY);
+        store(&mut flash, 0x110, CMD_PROGRAM);
+        store(&mut flash, 0x110, 0xf0);
+        assert_eq!(load(&mut flash, 0x110), STATUS_READY);
+        store(&mut flash, 0, CMD_READ_ARRAY);
+        assert_eq!(load(&mut flash, 0x110), 0xf0);
+
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image[0x110], 0xf0);
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, BufWriter},
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use sdl2::rect::Rect;
+
+use crate::utils::{
+    Exception,
+    channel::{Receiver, Sender, channel},
+    u32_to_u8,
+};
+
+use super::{
+    Device, Irq, Size,
+    sdl::{FrontBuffer, Frontend, FrontendCommand},
+    ysyx::YsyxEvent,
+};
+
+// VGACTL, relative to `ctl_base`: width << 16 | height, then sync: write 1 to
+// request a frame, reads back 0 once it is presented
+const VGACTL_SIZE: u32 = 0;
+const VGACTL_SYNC: u32 = 4;
+
+// in ysyx the pixels may grow up to the audio stream buffer
+pub(crate) const FB_MAX_SIZE: usize = 0x01000000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    /// 32 bits per pixel, 0x00RRGGBB.
+    #[default]
+    Xrgb8888,
+    /// 16 bits per pixel, RRRRRGGGGGGBBBBB.
+    Rgb565,
+}
+
+impl PixelFormat {
+    pub(crate) fn bytes(self) -> usize {
+        match self {
+            PixelFormat::Xrgb8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+
+    // 8-bit RGB of the pixel starting at `bytes`
+    fn rgb(self, bytes: &[u8]) -> [u8; 3] {
+        match self {
+            PixelFormat::Xrgb8888 => [bytes[2], bytes[1], bytes[0]],
+            PixelFormat::Rgb565 => {
+                let pixel = u16::from_le_bytes([bytes[0], bytes[1]]);
+                let (r, g, b) = (pixel >> 11, (pixel >> 5) & 0x3f, pixel & 0x1f);
+                [
+                    (r << 3 | r >> 2) as u8,
+                    (g << 2 | g >> 4) as u8,
+                    (b << 3 | b >> 2) as u8,
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FramebufferConfig {
+    /// The two VGACTL words.
+    pub ctl_base: u32,
+    /// The pixels, above the VGACTL words.
+    pub fb_base: u32,
+    /// Size in pixels, reported to the guest through VGACTL; neither can be
+    /// 0.
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// Keep the framebuffer visible only to the guest and through MMIO,
+    /// without a window or SDL at all.
+    pub headless: bool,
+    /// Present at most this many frames per second; syncs arriving sooner
+    /// are folded into the next frame. `None` presents every sync.
+    pub max_fps: Option<u32>,
+}
+
+impl FramebufferConfig {
+    pub fn new(ctl_base: u32, fb_base: u32, width: u16, height: u16) -> FramebufferConfig {
+        FramebufferConfig {
+            ctl_base,
+            fb_base,
+            width,
+            height,
+            format: PixelFormat::default(),
+            headless: false,
+            max_fps: None,
+        }
+    }
+}
+
+/// A memory-mapped framebuffer with the ysyx VGACTL interface, shown in an SDL
+/// window on a frontend thread unless headless.
+pub struct Framebuffer {
+    ctl_base: u32,
+    fb_base: u32,
+    frontend: Option<Frontend>,
+    vgactl: [u32; 2],
+    frame_interval: Option<Duration>,
+    last_frame: Option<Instant>,
+    frames: u64,
+    width: usize,
+    height: usize,
+    format: PixelFormat,
+    // raw framebuffer contents in `format`, packed into words; the guest
+    // draws into this back buffer and each sync copies it to the front
+    vmem: Vec<u32>,
+    front: Arc<Mutex<Vec<u32>>>,
+    // bounding box of pixels written since the last sync, as inclusive
+    // [x0, y0, x1, y1]
+    dirty: Option<[usize; 4]>,
+}
+
+impl Debug for Framebuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Framebuffer")
+            .field("vgactl", &self.vgactl)
+            .field("vmem", &self.vmem)
+            .finish()
+    }
+}
+
+impl Framebuffer {
+    /// Input from the window, if any, comes out of the returned receiver.
+    /// Fails if either dimension is 0, if the pixels don't fit in 16MiB, or
+    /// if the window can't be opened; SDL allows only one window per
+    /// process, so only one framebuffer, ysyx's included, can be shown.
+    pub fn new(config: &FramebufferConfig) -> io::Result<(Framebuffer, Receiver<YsyxEvent>)> {
+        let (events, receiver) = channel();
+        Ok((Framebuffer::with_events(config, events)?, receiver))
+    }
+
+    // like `new`, with window input going to `events`
+    pub(crate) fn with_events(
+        config: &FramebufferConfig,
+        events: Sender<YsyxEvent>,
+    ) -> io::Result<Framebuffer> {
+        let (width, height) = (config.width as usize, config.height as usize);
+        if width == 0 || height == 0 || width * height * config.format.bytes() > FB_MAX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "framebuffer size must be nonzero and fit in 16MiB",
+            ));
+        }
+        let words = (width * height * config.format.bytes()).div_ceil(4);
+        let front = Arc::new(Mutex::new(vec![0; words]));
+        let frontend = if config.headless {
+            None
+        } else {
+            Some(Frontend::spawn(
+                events,
+                FrontBuffer {
+                    pixels: front.clone(),
+                    width,
+                    height,
+                    format: config.format,
+                },
+            )?)
+        };
+        Ok(Framebuffer {
+            ctl_base: config.ctl_base,
+            fb_base: config.fb_base,
+            frontend,
+            vgactl: [((width << 16) | height) as u32, 0],
+            frame_interval: config
+                .max_fps
+                .filter(|&fps| fps > 0)
+                .map(|fps| Duration::from_secs(1) / fps),
+            last_frame: None,
+            frames: 0,
+            width,
+            height,
+            format: config.format,
+            vmem: vec![0; words],
+            front,
+            dirty: None,
+        })
+    }
+
+    // the window's frontend thread, which also carries ysyx audio
+    pub(crate) fn frontend(&self) -> Option<&Frontend> {
+        self.frontend.as_ref()
+    }
+
+    // whether `address` is VGACTL or in the pixel area
+    pub(crate) fn contains(&self, address: u32) -> bool {
+        (self.ctl_base..self.ctl_base + 8).contains(&address)
+            || (self.fb_base..self.fb_base + FB_MAX_SIZE as u32).contains(&address)
+    }
+
+    /// The framebuffer as the guest sees it: row-major pixels in the
+    /// configured format, one per word for `Xrgb8888` and two for `Rgb565`.
+    pub fn pixels(&self) -> &[u32] {
+        &self.vmem
+    }
+
+    /// Saves the framebuffer as an RGB PNG.
+    pub fn screenshot(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let bytes: Vec<u8> = self
+            .vmem
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let data: Vec<u8> = bytes
+            .chunks_exact(self.format.bytes())
+            .take(self.width * self.height)
+            .flat_map(|pixel| self.format.rgb(pixel))
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&data))
+            .map_err(io::Error::other)
+    }
+
+    /// Frames presented so far; coalesced syncs count once.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    // presents a requested frame now, or once the frame interval is up when
+    // capped; the sync register reads 1 until then
+    fn sync(&mut self) {
+        if let (Some(interval), Some(last)) = (self.frame_interval, self.last_frame)
+            && last.elapsed() < interval
+        {
+            return;
+        }
+        self.vgactl[1] = 0;
+        self.last_frame = Some(Instant::now());
+        self.frames += 1;
+        if let Some(dirty) = self.dirty_rect() {
+            self.present(Some(dirty));
+        }
+        self.dirty = None;
+    }
+
+    /// The region written since the last presented frame, in framebuffer
+    /// pixels.
+    pub fn dirty_rect(&self) -> Option<Rect> {
+        self.dirty.map(|[x0, y0, x1, y1]| {
+            Rect::new(
+                x0 as i32,
+                y0 as i32,
+                (x1 - x0 + 1) as u32,
+                (y1 - y0 + 1) as u32,
+            )
+        })
+    }
+
+    fn mark_dirty(&mut self, range: &Range<usize>) {
+        let bytes = self.format.bytes();
+        for pixel in [range.start / bytes, (range.end - 1) / bytes] {
+            // padding after the last pixel of an odd-sized 16-bit framebuffer
+            if pixel >= self.width * self.height {
+                continue;
+            }
+            let (x, y) = (pixel % self.width, pixel / self.width);
+            self.dirty = Some(match self.dirty {
+                Some([x0, y0, x1, y1]) => [x0.min(x), y0.min(y), x1.max(x), y1.max(y)],
+                None => [x, y, x, y],
+            });
+        }
+    }
+
+    /// A copy of the front buffer: the framebuffer as of the last presented
+    /// frame, unaffected by drawing since.
+    pub fn displayed_frame(&self) -> Vec<u32> {
+        self.front.lock().unwrap().clone()
+    }
+
+    // publishes vmem as the front buffer and has the frontend show it, only
+    // `region` of it needing a redraw if given
+    fn present(&mut self, region: Option<Rect>) {
+        self.front.lock().unwrap().copy_from_slice(&self.vmem);
+        if let Some(frontend) = &self.frontend {
+            frontend.send(FrontendCommand::Frame(region));
+        }
+    }
+
+    // bytes of vmem an access covers, None outside the framebuffer; any
+    // naturally aligned access up to a word is allowed so 16-bit pixels can
+    // be written one at a time
+    fn fb_range(&self, address: u32, size: Size) -> Option<Range<usize>> {
+        let offset = address.checked_sub(self.fb_base)? as usize;
+        let len = size.bytes();
+        (len <= 4 && size.is_aligned(address) && offset + len <= self.vmem.len() * 4)
+            .then_some(offset..offset + len)
+    }
+}
+
+impl Device for Framebuffer {
+    fn name(&self) -> &str {
+        "framebuffer"
+    }
+
+    // VGACTL up to the end of the pixels; accesses in between fault
+    fn address_range(&self) -> Range<u32> {
+        self.ctl_base..self.fb_base + (self.vmem.len() * 4) as u32
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {
+        if self.vgactl[1] != 0 {
+            self.sync();
+        }
+    }
+
+    // the window stays open; the screen is blanked
+    fn reset(&mut self) {
+        self.vgactl[1] = 0;
+        self.last_frame = None;
+        self.frames = 0;
+        self.vmem.fill(0);
+        self.dirty = None;
+        self.present(None);
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if let Some(range) = self.fb_range(address, size) {
+            let mut data = [0; 8];
+            let len = range.len();
+            data[..len].copy_from_slice(&u32_to_u8(&mut self.vmem)[range]);
+            return Ok(u64::from_le_bytes(data));
+        }
+        match (size, address.wrapping_sub(self.ctl_base)) {
+            (Size::_4, VGACTL_SIZE) => Ok(self.vgactl[0] as u64),
+            (Size::_4, VGACTL_SYNC) => Ok(self.vgactl[1] as u64),
+            _ => Err(Exception::LoadAccessFault { address, size }),
+        }
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if let Some(range) = self.fb_range(address, size) {
+            let len = range.len();
+            self.mark_dirty(&range);
+            u32_to_u8(&mut self.vmem)[range].copy_from_slice(&data.to_le_bytes()[..len]);
+            return Ok(());
+        }
+        match (size, address.wrapping_sub(self.ctl_base)) {
+            (Size::_4, VGACTL_SYNC) => {
+                // writing 1 requests a frame, anything else is ignored
+                if data & 1 != 0 {
+                    self.vgactl[1] = 1;
+                    self.sync();
+                }
+                Ok(())
+            }
+            _ => Err(Exception::StoreAccessFault { address, size }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTL: u32 = 0x1000;
+    const FB: u32 = 0x10000;
+
+    fn headless(width: u16, height: u16, format: PixelFormat) -> Framebuffer {
+        Framebuffer::new(&FramebufferConfig {
+            format,
+            headless: true,
+            ..FramebufferConfig::new(CTL, FB, width, height)
+        })
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn rgb565_pixels_pack_two_per_word() {
+        let mut fb = headless(3, 2, PixelFormat::Rgb565);
+        // pixel (1, 1) is the fifth, at byte 8
+        fb.write(FB + 8, Size::_2, 0xf800).unwrap();
+        assert_eq!(fb.pixels(), [0, 0, 0xf800]);
+        assert_eq!(fb.read(FB + 8, Size::_2), Ok(0xf800));
+        assert_eq!(fb.address_range(), CTL..FB + 12);
+
+        fb.write(CTL + VGACTL_SYNC, Size::_4, 1).unwrap();
+        let path = std::env::temp_dir().join(format!("fb-565-{}.png", std::process::id()));
+        fb.screenshot(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dirty_rect_covers_one_pixel() {
+        let mut fb = headless(10, 10, PixelFormat::Xrgb8888);
+        assert_eq!(fb.dirty_rect(), None);
+        fb.write(FB + 4 * (3 * 10 + 7), Size::_4, 0xffffff).unwrap();
+        assert_eq!(fb.dirty_rect(), Some(Rect::new(7, 3, 1, 1)));
+
+        fb.write(FB + 4 * (5 * 10 + 2), Size::_4, 0xffffff).unwrap();
+        assert_eq!(fb.dirty_rect(), Some(Rect::new(2, 3, 6, 3)));
+        fb.write(CTL + VGACTL_SYNC, Size::_4, 1).unwrap();
+        assert_eq!(fb.dirty_rect(), None);
+    }
+
+    #[test]
+    fn drawing_never_waits_on_the_frontend() {
+        let mut fb = headless(4, 4, PixelFormat::Xrgb8888);
+        let front = fb.front.clone();
+        // the frontend holding the front buffer doesn't stall guest drawing
+        let held = front.lock().unwrap();
+        for pixel in 0..16 {
+            fb.write(FB + 4 * pixel, Size::_4, pixel as u64).unwrap();
+        }
+        drop(held);
+
+        fb.write(CTL + VGACTL_SYNC, Size::_4, 1).unwrap();
+        assert_eq!(*front.lock().unwrap(), (0..16).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn sync_snapshots_the_back_buffer() {
+        let mut fb = headless(2, 2, PixelFormat::Xrgb8888);
+        fb.write(FB, Size::_4, 0x111111).unwrap();
+        fb.write(CTL + VGACTL_SYNC, Size::_4, 1).unwrap();
+        // drawing the next frame leaves the displayed one alone
+        fb.write(FB, Size::_4, 0x222222).unwrap();
+        fb.write(FB + 4, Size::_4, 0x333333).unwrap();
+        assert_eq!(fb.displayed_frame(), [0x111111, 0, 0, 0]);
+        assert_eq!(fb.pixels(), [0x222222, 0x333333, 0, 0]);
+    }
+
+    #[test]
+    fn standalone_framebuffers_blit_independently() {
+        let mut first = headless(4, 2, PixelFormat::default());
+        let mut second = headless(4, 2, PixelFormat::default());
+        for pixel in 0..8 {
+            first
+                .write(FB + pixel * 4, Size::_4, 0x0001_0101 * pixel as u64)
+                .unwrap();
+        }
+        first.write(CTL + 4, Size::_4, 1).unwrap();
+        second.write(CTL + 4, Size::_4, 1).unwrap();
+        let pattern: Vec<u32> = (0..8).map(|pixel| 0x0001_0101 * pixel).collect();
+        assert_eq!(first.displayed_frame(), pattern);
+        assert_eq!(second.displayed_frame(), [0; 8]);
+        assert_eq!(first.read(CTL, Size::_4), Ok(4 << 16 | 2));
+        assert!(first.read(FB + 8 * 4, Size::_4).is_err());
+    }
+
+    #[test]
+    fn zero_or_oversized_screens_are_rejected() {
+        for (width, height) in [(0, 480), (640, 0), (4096, 4096)] {
+            let result = Framebuffer::new(&FramebufferConfig {
+                headless: true,
+                ..FramebufferConfig::new(CTL, FB, width, height)
+            });
+            assert_eq!(
+                result.err().map(|e| e.kind()),
+                Some(io::ErrorKind::InvalidInput)
+            );
+        }
+    }
+}
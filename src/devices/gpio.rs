@@ -0,0 +1,196 @@
+use std::ops::Range;
+
+use crate::utils::{
+    Exception, Size,
+    channel::{Receiver, Sender, channel},
+};
+
+use super::{Device, Irq};
+
+pub(crate) const GPIO_SIZE: u32 = 0x100;
+
+// pin levels: inputs as driven by the host, outputs as driven by the guest
+const GPIO_VALUE: u32 = 0x00;
+const GPIO_OUTPUT: u32 = 0x04;
+// 1 for output
+const GPIO_DIRECTION: u32 = 0x08;
+const GPIO_IRQ_ENABLE: u32 = 0x0c;
+// input pins that changed level, write 1 to clear
+const GPIO_IRQ_PENDING: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GpioConfig {
+    pub base: u32,
+    pub interrupt_id: u32,
+}
+
+impl GpioConfig {
+    pub fn new(base: u32, interrupt_id: u32) -> GpioConfig {
+        GpioConfig { base, interrupt_id }
+    }
+}
+
+/// A level change on an input pin, sent by the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpioInput {
+    pub pin: u8,
+    pub high: bool,
+}
+
+/// A 32-pin GPIO controller. The host drives input pins through the returned
+/// sender and observes the pins the guest drives, as a bitmask sent whenever it
+/// changes, through the returned receiver.
+#[derive(Debug)]
+pub struct Gpio {
+    base: u32,
+    interrupt_id: u32,
+    receiver: Receiver<GpioInput>,
+    sender: Sender<u32>,
+    inputs: u32,
+    output: u32,
+    direction: u32,
+    irq_enable: u32,
+    irq_pending: u32,
+    // the last pin state sent to the host
+    driven: u32,
+}
+
+impl Gpio {
+    pub fn new(config: &GpioConfig) -> (Gpio, Sender<GpioInput>, Receiver<u32>) {
+        let (input_send, input_recv) = channel();
+        let (output_send, output_recv) = channel();
+        (
+            Gpio {
+                base: config.base,
+                interrupt_id: config.interrupt_id,
+                receiver: input_recv,
+                sender: output_send,
+                inputs: 0,
+                output: 0,
+                direction: 0,
+                irq_enable: 0,
+                irq_pending: 0,
+                driven: 0,
+            },
+            input_send,
+            output_recv,
+        )
+    }
+
+    fn value(&self) -> u32 {
+        (self.inputs & !self.direction) | (self.output & self.direction)
+    }
+
+    fn update_outputs(&mut self) {
+        let driven = self.output & self.direction;
+        if driven != self.driven {
+            self.driven = driven;
+            self.sender.send(driven);
+        }
+    }
+}
+
+impl Device for Gpio {
+    fn name(&self) -> &str {
+        "gpio"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + GPIO_SIZE
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        while self.receiver.avaliable() {
+            let Some(GpioInput { pin, high }) = self.receiver.recv() else {
+                break;
+            };
+            let Some(mask) = 1u32.checked_shl(pin as u32) else {
+                continue;
+            };
+            let inputs = if high {
+                self.inputs | mask
+            } else {
+                self.inputs & !mask
+            };
+            // only pins configured as inputs report changes
+            self.irq_pending |= (inputs ^ self.inputs) & !self.direction;
+            self.inputs = inputs;
+        }
+        irq.irq(self.interrupt_id, self.irq_pending & self.irq_enable != 0);
+    }
+
+    // the pins the host drives keep their levels
+    fn reset(&mut self) {
+        self.output = 0;
+        self.direction = 0;
+        self.irq_enable = 0;
+        self.irq_pending = 0;
+        self.update_outputs();
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            GPIO_VALUE => self.value(),
+            GPIO_OUTPUT => self.output,
+            GPIO_DIRECTION => self.direction,
+            GPIO_IRQ_ENABLE => self.irq_enable,
+            GPIO_IRQ_PENDING => self.irq_pending,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            GPIO_OUTPUT => self.output = data,
+            GPIO_DIRECTION => self.direction = data,
+            GPIO_IRQ_ENABLE => self.irq_enable = data,
+            GPIO_IRQ_PENDING => self.irq_pending &= !data,
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        self.update_outputs();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_3000;
+
+    fn clk(gpio: &mut Gpio) -> Option<(u32, bool)> {
+        let mut irq = Irq::new();
+        gpio.clk(&mut irq);
+        irq.next()
+    }
+
+    #[test]
+    fn outputs_reach_the_host_and_inputs_interrupt() {
+        let (mut gpio, inputs, outputs) = Gpio::new(&GpioConfig::new(BASE, 5));
+        gpio.write(BASE + GPIO_DIRECTION, Size::_4, 0x0f).unwrap();
+        gpio.write(BASE + GPIO_OUTPUT, Size::_4, 0x35).unwrap();
+        // only pins set as outputs are driven
+        assert_eq!(outputs.recv(), Some(0x05));
+
+        inputs.send(GpioInput { pin: 8, high: true });
+        assert_eq!(clk(&mut gpio), Some((5, false)));
+        assert_eq!(gpio.read(BASE + GPIO_VALUE, Size::_4), Ok(0x105));
+
+        gpio.write(BASE + GPIO_IRQ_ENABLE, Size::_4, 1 << 9)
+            .unwrap();
+        inputs.send(GpioInput { pin: 9, high: true });
+        assert_eq!(clk(&mut gpio), Some((5, true)));
+        assert_eq!(gpio.read(BASE + GPIO_IRQ_PENDING, Size::_4), Ok(0x300));
+        gpio.write(BASE + GPIO_IRQ_PENDING, Size::_4, 0x300)
+            .unwrap();
+        assert_eq!(clk(&mut gpio), Some((5, false)));
+    }
+}
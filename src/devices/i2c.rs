@@ -0,0 +1,229 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+pub(crate) const I2C_SIZE: u32 = 0x100;
+
+// write: the byte for the next WRITE command; read: the byte the last READ
+// command received
+const I2C_DATA: u32 = 0x00;
+const I2C_CMD: u32 = 0x04;
+const I2C_STATUS: u32 = 0x08;
+
+// commands, combinable and carried out in this order
+const I2C_CMD_START: u32 = 0b00001;
+const I2C_CMD_WRITE: u32 = 0b00010;
+const I2C_CMD_READ: u32 = 0b00100;
+// with READ: answer NACK, telling the slave it was the last byte
+const I2C_CMD_NACK: u32 = 0b01000;
+const I2C_CMD_STOP: u32 = 0b10000;
+
+// the last byte written was not acknowledged
+const I2C_STATUS_NACK: u32 = 0b01;
+// between START and STOP
+const I2C_STATUS_BUSY: u32 = 0b10;
+
+/// A device on the I2C bus, e.g. a sensor or an EEPROM.
+pub trait I2cSlave: std::fmt::Debug {
+    /// Called when a (repeated) START addresses this slave, with the
+    /// direction of the transfer that follows.
+    fn start(&mut self, _read: bool) {}
+
+    /// Receives one byte from the controller; returns whether to ACK it.
+    fn write(&mut self, data: u8) -> bool;
+
+    /// Sends one byte to the controller. `last` is set when the controller
+    /// answers it with NACK, ending the read.
+    fn read(&mut self, last: bool) -> u8;
+
+    fn stop(&mut self) {}
+}
+
+// where the controller is within a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Idle,
+    // after START, the next byte written is the address
+    Address,
+    // talking to the slave at this address
+    Selected(u8),
+    // the address wasn't acknowledged; data goes nowhere until STOP
+    Unselected,
+}
+
+/// An I2C controller driven one command at a time through the CMD register.
+/// Each command completes immediately. Attach slaves with `attach`.
+#[derive(Debug)]
+pub struct I2c {
+    base: u32,
+    slaves: BTreeMap<u8, Box<dyn I2cSlave>>,
+    state: State,
+    tx: u8,
+    rx: u8,
+    status: u32,
+}
+
+impl I2c {
+    pub fn new(base: u32) -> I2c {
+        I2c {
+            base,
+            slaves: BTreeMap::new(),
+            state: State::Idle,
+            tx: 0,
+            rx: 0,
+            status: 0,
+        }
+    }
+
+    /// Connects `slave` at 7-bit `address`, replacing any slave already
+    /// there.
+    pub fn attach(&mut self, address: u8, slave: Box<dyn I2cSlave>) {
+        assert!(address < 0x80, "I2C addresses are 7 bits");
+        self.slaves.insert(address, slave);
+    }
+
+    fn selected(&mut self) -> Option<&mut Box<dyn I2cSlave>> {
+        match self.state {
+            State::Selected(address) => self.slaves.get_mut(&address),
+            _ => None,
+        }
+    }
+
+    fn command(&mut self, command: u32) {
+        if command & I2C_CMD_START != 0 {
+            self.state = State::Address;
+            self.status |= I2C_STATUS_BUSY;
+        }
+        if command & I2C_CMD_WRITE != 0 {
+            let ack = match self.state {
+                State::Address => {
+                    let address = self.tx >> 1;
+                    match self.slaves.get_mut(&address) {
+                        Some(slave) => {
+                            slave.start(self.tx & 1 != 0);
+                            self.state = State::Selected(address);
+                            true
+                        }
+                        None => {
+                            self.state = State::Unselected;
+                            false
+                        }
+                    }
+                }
+                _ => {
+                    let data = self.tx;
+                    self.selected().is_some_and(|slave| slave.write(data))
+                }
+            };
+            if ack {
+                self.status &= !I2C_STATUS_NACK;
+            } else {
+                self.status |= I2C_STATUS_NACK;
+            }
+        }
+        if command & I2C_CMD_READ != 0 {
+            let last = command & I2C_CMD_NACK != 0;
+            // SDA floats high with no slave driving it
+            self.rx = self.selected().map_or(0xFF, |slave| slave.read(last));
+        }
+        if command & I2C_CMD_STOP != 0 {
+            if let Some(slave) = self.selected() {
+                slave.stop();
+            }
+            self.state = State::Idle;
+            self.status &= !I2C_STATUS_BUSY;
+        }
+    }
+}
+
+impl Device for I2c {
+    fn name(&self) -> &str {
+        "i2c"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + I2C_SIZE
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {}
+
+    fn reset(&mut self) {
+        if let Some(slave) = self.selected() {
+            slave.stop();
+        }
+        self.state = State::Idle;
+        self.tx = 0;
+        self.rx = 0;
+        self.status = 0;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            I2C_DATA => self.rx as u32,
+            I2C_STATUS => self.status,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        match address - self.base {
+            I2C_DATA => self.tx = data as u8,
+            I2C_CMD => self.command(data as u32),
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_5000;
+
+    // always answers with the same byte
+    #[derive(Debug)]
+    struct Canned(u8);
+
+    impl I2cSlave for Canned {
+        fn write(&mut self, _data: u8) -> bool {
+            true
+        }
+
+        fn read(&mut self, _last: bool) -> u8 {
+            self.0
+        }
+    }
+
+    fn command(i2c: &mut I2c, data: u8, command: u32) -> u64 {
+        i2c.write(BASE + I2C_DATA, Size::_4, data as u64).unwrap();
+        i2c.write(BASE + I2C_CMD, Size::_4, command as u64).unwrap();
+        i2c.read(BASE + I2C_STATUS, Size::_4).unwrap()
+    }
+
+    #[test]
+    fn read_returns_the_slave_byte() {
+        let mut i2c = I2c::new(BASE);
+        i2c.attach(0x50, Box::new(Canned(0x42)));
+        let status = command(&mut i2c, 0x50 << 1 | 1, I2C_CMD_START | I2C_CMD_WRITE);
+        assert_eq!(status, I2C_STATUS_BUSY as u64);
+        let status = command(&mut i2c, 0, I2C_CMD_READ | I2C_CMD_NACK | I2C_CMD_STOP);
+        assert_eq!(status, 0);
+        assert_eq!(i2c.read(BASE + I2C_DATA, Size::_4), Ok(0x42));
+
+        // nobody at 0x51
+        let status = command(&mut i2c, 0x51 << 1 | 1, I2C_CMD_START | I2C_CMD_WRITE);
+        assert_eq!(status, (I2C_STATUS_BUSY | I2C_STATUS_NACK) as u64);
+        command(&mut i2c, 0, I2C_CMD_READ | I2C_CMD_STOP);
+        assert_eq!(i2c.read(BASE + I2C_DATA, Size::_4), Ok(0xff));
+    }
+}
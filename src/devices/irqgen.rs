@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+pub(crate) const IRQGEN_SIZE: u32 = 0x100;
+
+// write an interrupt id to raise or lower its line
+const IRQGEN_RAISE: u32 = 0x00;
+const IRQGEN_LOWER: u32 = 0x04;
+// raise PERIODIC_LINE every PERIOD device clocks, 0 to stop; the line stays
+// up until lowered through LOWER
+const IRQGEN_PERIOD: u32 = 0x08;
+const IRQGEN_PERIODIC_LINE: u32 = 0x0c;
+// periods elapsed, read-only
+const IRQGEN_COUNT: u32 = 0x10;
+
+/// A test device letting the guest drive PLIC interrupt lines directly, once or
+/// periodically. Line changes reach the PLIC on the next device clock.
+#[derive(Debug)]
+pub struct IrqGen {
+    base: u32,
+    // line levels to pass on at the next clock; the last write to a line wins
+    changes: BTreeMap<u32, bool>,
+    period: u32,
+    periodic_line: u32,
+    counter: u32,
+    count: u32,
+}
+
+impl IrqGen {
+    pub fn new(base: u32) -> IrqGen {
+        IrqGen {
+            base,
+            changes: BTreeMap::new(),
+            period: 0,
+            periodic_line: 0,
+            counter: 0,
+            count: 0,
+        }
+    }
+}
+
+impl Device for IrqGen {
+    fn name(&self) -> &str {
+        "irqgen"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + IRQGEN_SIZE
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        if self.period != 0 {
+            self.counter += 1;
+            if self.counter >= self.period {
+                self.counter = 0;
+                self.count = self.count.wrapping_add(1);
+                self.changes.insert(self.periodic_line, true);
+            }
+        }
+        for (line, level) in std::mem::take(&mut self.changes) {
+            irq.irq(line, level);
+        }
+    }
+
+    // lines already raised stay up until the PLIC itself is reset
+    fn reset(&mut self) {
+        self.changes.clear();
+        self.period = 0;
+        self.periodic_line = 0;
+        self.counter = 0;
+        self.count = 0;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            IRQGEN_PERIOD => self.period,
+            IRQGEN_PERIODIC_LINE => self.periodic_line,
+            IRQGEN_COUNT => self.count,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            IRQGEN_RAISE => {
+                self.changes.insert(data, true);
+            }
+            IRQGEN_LOWER => {
+                self.changes.insert(data, false);
+            }
+            IRQGEN_PERIOD => {
+                self.period = data;
+                self.counter = 0;
+            }
+            IRQGEN_PERIODIC_LINE => self.periodic_line = data,
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_b000;
+
+    fn clk(irqgen: &mut IrqGen) -> Vec<(u32, bool)> {
+        let mut irq = Irq::new();
+        irqgen.clk(&mut irq);
+        irq.collect()
+    }
+
+    #[test]
+    fn periodic_line_rises_every_period() {
+        let mut irqgen = IrqGen::new(BASE);
+        irqgen
+            .write(BASE + IRQGEN_PERIODIC_LINE, Size::_4, 9)
+            .unwrap();
+        irqgen.write(BASE + IRQGEN_PERIOD, Size::_4, 4).unwrap();
+        let raised: Vec<usize> = (1..=12)
+            .filter(|_| clk(&mut irqgen) == [(9, true)])
+            .collect();
+        assert_eq!(raised, [4, 8, 12]);
+        assert_eq!(irqgen.read(BASE + IRQGEN_COUNT, Size::_4), Ok(3));
+
+        irqgen.write(BASE + IRQGEN_LOWER, Size::_4, 9).unwrap();
+        irqgen.write(BASE + IRQGEN_RAISE, Size::_4, 3).unwrap();
+        assert_eq!(clk(&mut irqgen), [(9, false), (3, true)]);
+    }
+}
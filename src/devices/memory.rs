@@ -1,8 +1,11 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
 
 use crate::utils::Exception;
 
-use super::{Device, Irq, Size};
+use super::{Device, DmaPort, Irq, Size};
 
 pub(crate) const MEMORY_SIZE: usize = 1024 * 1024 * 1024;
 pub(crate) const MEMORY_START: u32 = 0x80000000;
@@ -24,50 +27,234 @@ impl Memory {
     }
 }
 
+// offset of an access into memory, None if any byte of it falls outside
+fn checked_offset(address: u32, size: Size) -> Option<usize> {
+    let offset = address.checked_sub(MEMORY_START)? as usize;
+    (offset + size.bytes() <= MEMORY_SIZE).then_some(offset)
+}
+
+// whether `len` bytes at `address` lie entirely in RAM, the only memory a
+// bus master can reach; devices check this before allocating a buffer for a
+// transfer
+pub(crate) fn in_ram(address: u32, len: u64) -> bool {
+    address >= MEMORY_START && (address - MEMORY_START) as u64 + len <= MEMORY_SIZE as u64
+}
+
+// like `checked_offset`, for a DMA transfer of `len` bytes
+fn checked_range(address: u32, len: usize) -> Option<Range<usize>> {
+    let offset = address.checked_sub(MEMORY_START)? as usize;
+    (offset + len <= MEMORY_SIZE).then_some(offset..offset + len)
+}
+
+impl DmaPort for Memory {
+    fn dma_read(&mut self, address: u32, data: &mut [u8]) -> Result<(), Exception> {
+        let range = checked_range(address, data.len()).ok_or(Exception::LoadAccessFault {
+            address,
+            size: Size::_1,
+        })?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.mem.wrapping_add(range.start),
+                data.as_mut_ptr(),
+                range.len(),
+            );
+        }
+        Ok(())
+    }
+
+    fn dma_write(&mut self, address: u32, data: &[u8]) -> Result<(), Exception> {
+        let range = checked_range(address, data.len()).ok_or(Exception::StoreAccessFault {
+            address,
+            size: Size::_1,
+        })?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mem.wrapping_add(range.start),
+                range.len(),
+            );
+        }
+        Ok(())
+    }
+}
+
 impl Device for Memory {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        MEMORY_START..MEMORY_END + 1
+    }
+
     fn clk(&mut self, _irq: &mut Irq) {}
 
+    // a fresh allocation rather than a fill, so untouched pages stay unmapped
+    fn reset(&mut self) {
+        *self = Memory::new();
+    }
+
     fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
-        let address = address - MEMORY_START;
+        if !size.is_aligned(address) {
+            return Err(Exception::LoadAddressMisaligned { address, size });
+        }
+        let offset =
+            checked_offset(address, size).ok_or(Exception::LoadAccessFault { address, size })?;
         match size {
-            Size::_1 => Ok((unsafe { *(self.mem.wrapping_add(address as usize)) }) as u64),
+            Size::_1 => Ok((unsafe { *(self.mem.wrapping_add(offset)) }) as u64),
             Size::_2 => {
-                Ok(
-                    u16::from_le(unsafe { *(self.mem.wrapping_add(address as usize) as *const _) })
-                        as u64,
-                )
+                Ok(u16::from_le(unsafe { *(self.mem.wrapping_add(offset) as *const _) }) as u64)
             }
             Size::_4 => {
-                Ok(
-                    u32::from_le(unsafe { *(self.mem.wrapping_add(address as usize) as *const _) })
-                        as u64,
-                )
+                Ok(u32::from_le(unsafe { *(self.mem.wrapping_add(offset) as *const _) }) as u64)
             }
             Size::_8 => Ok(u64::from_le(unsafe {
-                *(self.mem.wrapping_add(address as usize) as *const _)
+                *(self.mem.wrapping_add(offset) as *const _)
             })),
+            Size::_16 => Err(Exception::LoadAccessFault { address, size }),
         }
     }
 
     fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
-        let address = address - MEMORY_START;
+        if !size.is_aligned(address) {
+            return Err(Exception::StoreAddressMisaligned { address, size });
+        }
+        let offset =
+            checked_offset(address, size).ok_or(Exception::StoreAccessFault { address, size })?;
         match size {
             Size::_1 => unsafe {
-                *(self.mem.wrapping_add(address as usize)) = data as u8;
+                *(self.mem.wrapping_add(offset)) = data as u8;
                 Ok(())
             },
             Size::_2 => unsafe {
-                *(self.mem.wrapping_add(address as usize) as *mut _) = (data as u16).to_le();
+                *(self.mem.wrapping_add(offset) as *mut _) = (data as u16).to_le();
                 Ok(())
             },
             Size::_4 => unsafe {
-                *(self.mem.wrapping_add(address as usize) as *mut _) = (data as u32).to_le();
+                *(self.mem.wrapping_add(offset) as *mut _) = (data as u32).to_le();
                 Ok(())
             },
             Size::_8 => unsafe {
-                *(self.mem.wrapping_add(address as usize) as *mut _) = data.to_le();
+                *(self.mem.wrapping_add(offset) as *mut _) = data.to_le();
                 Ok(())
             },
+            Size::_16 => Err(Exception::StoreAccessFault { address, size }),
+        }
+    }
+
+    fn read_u128(&mut self, address: u32) -> Result<u128, Exception> {
+        if !Size::_16.is_aligned(address) {
+            return Err(Exception::LoadAddressMisaligned {
+                address,
+                size: Size::_16,
+            });
         }
+        let offset = checked_offset(address, Size::_16).ok_or(Exception::LoadAccessFault {
+            address,
+            size: Size::_16,
+        })?;
+        Ok(u128::from_le(unsafe {
+            *(self.mem.wrapping_add(offset) as *const _)
+        }))
+    }
+
+    fn write_u128(&mut self, address: u32, data: u128) -> Result<(), Exception> {
+        if !Size::_16.is_aligned(address) {
+            return Err(Exception::StoreAddressMisaligned {
+                address,
+                size: Size::_16,
+            });
+        }
+        let offset = checked_offset(address, Size::_16).ok_or(Exception::StoreAccessFault {
+            address,
+            size: Size::_16,
+        })?;
+        unsafe {
+            *(self.mem.wrapping_add(offset) as *mut _) = data.to_le();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quad_word_round_trip() {
+        let mut memory = Memory::new();
+        let data = 0x0011_2233_4455_6677_8899_aabb_ccdd_eeffu128;
+        memory.write_u128(MEMORY_START + 16, data).unwrap();
+        assert_eq!(memory.read_u128(MEMORY_START + 16), Ok(data));
+        // little-endian, so the low half sits first
+        assert_eq!(memory.read(MEMORY_START + 16, Size::_8), Ok(data as u64));
+        assert_eq!(
+            memory.read(MEMORY_START + 24, Size::_8),
+            Ok((data >> 64) as u64)
+        );
+        assert_eq!(
+            memory.read_u128(MEMORY_START + 8),
+            Err(Exception::LoadAddressMisaligned {
+                address: MEMORY_START + 8,
+                size: Size::_16,
+            })
+        );
+    }
+
+    #[test]
+    fn misaligned_and_access_faults_differ() {
+        let mut memory = Memory::new();
+        assert_eq!(
+            memory.read(MEMORY_START + 2, Size::_4),
+            Err(Exception::LoadAddressMisaligned {
+                address: MEMORY_START + 2,
+                size: Size::_4,
+            })
+        );
+        assert_eq!(
+            memory.write(MEMORY_START + 1, Size::_2, 0),
+            Err(Exception::StoreAddressMisaligned {
+                address: MEMORY_START + 1,
+                size: Size::_2,
+            })
+        );
+        assert!(matches!(
+            memory.read(0x1000, Size::_4),
+            Err(Exception::LoadAccessFault { .. })
+        ));
+        assert!(matches!(
+            memory.write(0x1000, Size::_4, 0),
+            Err(Exception::StoreAccessFault { .. })
+        ));
+    }
+
+    #[test]
+    fn out_of_range_reports_the_address() {
+        let mut memory = Memory::new();
+        assert_eq!(
+            memory.read(0x1234, Size::_4),
+            Err(Exception::LoadAccessFault {
+                address: 0x1234,
+                size: Size::_4,
+            })
+        );
+        // the last word fits, the one just past the end doesn't
+        assert!(memory.read(MEMORY_END - 3, Size::_4).is_ok());
+        assert_eq!(
+            memory.write(MEMORY_END + 1, Size::_8, 0),
+            Err(Exception::StoreAccessFault {
+                address: MEMORY_END + 1,
+                size: Size::_8,
+            })
+        );
+    }
+
+    #[test]
+    fn in_ram_covers_exactly_the_memory_range() {
+        assert!(in_ram(MEMORY_START, MEMORY_SIZE as u64));
+        assert!(in_ram(MEMORY_END, 1));
+        assert!(!in_ram(MEMORY_END, 2));
+        assert!(!in_ram(MEMORY_START - 1, 1));
+        assert!(!in_ram(MEMORY_START, u32::MAX as u64 * 2));
     }
 }
@@ -1,11 +1,28 @@
 use crate::utils::Size;
+use std::ops::Range;
 
+pub mod clint;
+pub mod dma;
+pub mod flash;
+pub mod framebuffer;
+pub mod gpio;
+pub mod i2c;
+pub mod irqgen;
 pub(crate) mod memory;
-pub(crate) mod plic;
-pub(crate) mod uart;
-pub(crate) mod ysyx;
+pub mod plic;
+pub mod pwm;
+pub mod rng;
+pub mod rom;
+pub(crate) mod sdl;
+pub mod spi;
+pub mod syscon;
+pub mod uart;
+pub mod virtio;
+pub mod watchdog;
+pub mod ysyx;
 
-pub(crate) struct Irq {
+/// Interrupt lines raised or lowered by devices during one `Device::clk`.
+pub struct Irq {
     irqs: Vec<(u32, bool)>,
 }
 
@@ -14,7 +31,7 @@ impl Irq {
         Irq { irqs: Vec::new() }
     }
 
-    pub(crate) fn irq(&mut self, irq: u32, enable: bool) {
+    pub fn irq(&mut self, irq: u32, enable: bool) {
         self.irqs.push((irq, enable));
     }
 }
@@ -27,9 +44,43 @@ impl Iterator for Irq {
     }
 }
 
-pub(crate) trait Device {
+/// A memory-mapped device. Addresses passed to `read`/`write` are absolute,
+/// not offsets into `address_range`.
+pub trait Device: std::fmt::Debug {
+    fn name(&self) -> &str;
+    fn address_range(&self) -> Range<u32>;
     fn clk(&mut self, irq: &mut Irq);
     fn read(&mut self, address: u32, size: Size) -> Result<u64, crate::utils::Exception>;
     fn write(&mut self, address: u32, size: Size, data: u64)
     -> Result<(), crate::utils::Exception>;
+
+    // `Size::_16` accesses don't fit the u64 data path above; devices that
+    // support them override these
+    fn read_u128(&mut self, address: u32) -> Result<u128, crate::utils::Exception> {
+        Err(crate::utils::Exception::LoadAccessFault {
+            address,
+            size: Size::_16,
+        })
+    }
+    fn write_u128(&mut self, address: u32, _data: u128) -> Result<(), crate::utils::Exception> {
+        Err(crate::utils::Exception::StoreAccessFault {
+            address,
+            size: Size::_16,
+        })
+    }
+
+    // returns the device to its power-on state, keeping host-side
+    // connections such as channels and windows
+    fn reset(&mut self) {}
+
+    // runs any transfer the device has queued as bus master; called by the
+    // bus after each write to the device, and before each clock of devices
+    // added with `Bus::add_device`
+    fn dma(&mut self, _port: &mut dyn DmaPort) {}
+}
+
+/// Guest RAM as seen by a bus-mastering device.
+pub trait DmaPort {
+    fn dma_read(&mut self, address: u32, data: &mut [u8]) -> Result<(), crate::utils::Exception>;
+    fn dma_write(&mut self, address: u32, data: &[u8]) -> Result<(), crate::utils::Exception>;
 }
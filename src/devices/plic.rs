@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use crate::utils::Exception;
 
 use super::{Device, Irq, Size};
@@ -17,7 +19,6 @@ pub(crate) const PLIC_SOURCE_ENABLE_END: u32 = PLIC_START + 0x1F1FFF;
 pub(crate) const PLIC_THRESHOLD_CLIAM_COMPLETE_START: u32 = PLIC_START + 0x200000;
 pub(crate) const PLIC_THRESHOLD_CLIAM_COMPLETE_END: u32 = PLIC_START + 0x3FFFFFF;
 
-// TODO: hart count
 const HART_COUNT: usize = 1;
 const INTERRUPT_COUNT: usize = 64;
 
@@ -45,42 +46,224 @@ impl<T: Clone + Copy> Pair<T> {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IrqStats {
+    // rising edges of the source's line
+    pub raised: u64,
+    pub claimed: u64,
+}
+
+// per-hart state is indexed by context / 2, the machine/supervisor half by
+// context % 2
 #[derive(Debug)]
-pub(crate) struct Plic {
-    priorities: [u32; 1023],
+pub struct Plic {
+    // indexed by interrupt id, sized by the number of configured sources
+    priorities: Vec<u32>,
     pending: [u32; 32],
-    enable: [Pair<[u32; 32]>; HART_COUNT * 2],
-    threshold: [Pair<u32>; HART_COUNT * 2],
-    claimed: [Pair<[bool; 1024]>; HART_COUNT * 2],
+    // gateway configuration and the last line level seen, indexed by id
+    edge_triggered: Vec<bool>,
+    lines: Vec<bool>,
+    enable: Vec<Pair<[u32; 32]>>,
+    threshold: Vec<Pair<u32>>,
+    claimed: Vec<Pair<[bool; 1024]>>,
     update: bool,
+    stats: Vec<IrqStats>,
 }
 
 impl Plic {
     pub(crate) fn new() -> Plic {
+        Plic::with_harts(HART_COUNT)
+    }
+
+    /// A PLIC serving `harts` harts, i.e. `harts * 2` contexts. `harts` must
+    /// be at least 1.
+    pub fn with_harts(harts: usize) -> Plic {
+        Plic::with_config(harts, INTERRUPT_COUNT)
+    }
+
+    /// A PLIC serving `harts` harts with interrupt ids `1..sources`
+    /// (id 0 is reserved), where `sources` is at most 1024.
+    pub fn with_config(harts: usize, sources: usize) -> Plic {
+        assert!(harts >= 1, "the PLIC needs at least one hart");
+        assert!(
+            sources <= 1024,
+            "the PLIC supports at most 1024 sources (ids 1..1024)"
+        );
         Plic {
-            priorities: [0; 1023],
+            priorities: vec![0; sources],
             pending: [0; 32],
-            enable: [Pair {
-                machine: [0; 32],
-                supervisor: [0; 32],
-            }; HART_COUNT * 2],
-            threshold: [Pair {
-                machine: 0,
-                supervisor: 0,
-            }; HART_COUNT * 2],
-            claimed: [Pair {
-                machine: [false; 1024],
-                supervisor: [false; 1024],
-            }; HART_COUNT * 2],
+            edge_triggered: vec![false; sources],
+            lines: vec![false; sources],
+            enable: vec![
+                Pair {
+                    machine: [0; 32],
+                    supervisor: [0; 32],
+                };
+                harts
+            ],
+            threshold: vec![
+                Pair {
+                    machine: 0,
+                    supervisor: 0,
+                };
+                harts
+            ],
+            claimed: vec![
+                Pair {
+                    machine: [false; 1024],
+                    supervisor: [false; 1024],
+                };
+                harts
+            ],
             update: false,
+            stats: vec![IrqStats::default(); sources],
+        }
+    }
+
+    /// Returns every register to its power-on value. The hart/source
+    /// configuration and trigger modes are kept.
+    pub fn reset(&mut self) {
+        self.priorities.fill(0);
+        self.pending = [0; 32];
+        self.lines.fill(false);
+        for enable in &mut self.enable {
+            enable.machine = [0; 32];
+            enable.supervisor = [0; 32];
+        }
+        for threshold in &mut self.threshold {
+            threshold.machine = 0;
+            threshold.supervisor = 0;
+        }
+        for claimed in &mut self.claimed {
+            claimed.machine = [false; 1024];
+            claimed.supervisor = [false; 1024];
+        }
+        self.update = false;
+    }
+
+    /// Serializes the full interrupt state, configuration included, so a
+    /// snapshot can restore it with `from_bytes`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = vec![
+            self.enable.len() as u32,
+            self.sources() as u32,
+            self.update as u32,
+        ];
+        words.extend(&self.priorities);
+        words.extend(self.pending);
+        words.extend(pack_bits(&self.edge_triggered));
+        words.extend(pack_bits(&self.lines));
+        for hart in 0..self.enable.len() {
+            for mode in 0..2 {
+                words.extend(self.enable[hart].at(mode));
+                words.push(*self.threshold[hart].at(mode));
+                words.extend(pack_bits(self.claimed[hart].at(mode)));
+            }
+        }
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    /// Restores a PLIC saved with `to_bytes`, `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Plic> {
+        if !bytes.len().is_multiple_of(4) {
+            return None;
         }
+        let mut words = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()));
+        let harts = words.next()? as usize;
+        let sources = words.next()? as usize;
+        // every hart takes well over a byte, so this bounds the allocation
+        if harts == 0 || harts > bytes.len() || sources > 1024 {
+            return None;
+        }
+        let mut plic = Plic::with_config(harts, sources);
+        plic.update = words.next()? != 0;
+        for priority in &mut plic.priorities {
+            *priority = words.next()?;
+        }
+        for pending in &mut plic.pending {
+            *pending = words.next()?;
+        }
+        unpack_bits(&mut words, &mut plic.edge_triggered)?;
+        unpack_bits(&mut words, &mut plic.lines)?;
+        for hart in 0..harts {
+            for mode in 0..2 {
+                for enable in plic.enable[hart].at_mut(mode) {
+                    *enable = words.next()?;
+                }
+                *plic.threshold[hart].at_mut(mode) = words.next()?;
+                unpack_bits(&mut words, plic.claimed[hart].at_mut(mode))?;
+            }
+        }
+        words.next().is_none().then_some(plic)
+    }
+
+    /// Drops every pending interrupt while keeping priorities and enables.
+    /// Level sources whose line is still high pend again on their next report.
+    pub fn clear_pending(&mut self) {
+        self.pending = [0; 32];
+        self.update = true;
+    }
+
+    fn contexts(&self) -> usize {
+        self.enable.len() * 2
+    }
+
+    fn sources(&self) -> usize {
+        self.priorities.len()
+    }
+
+    /// Switches `irq` between level (the default) and edge triggering. An
+    /// edge-triggered source latches pending on a rising edge and stays
+    /// pending until claimed, even after its line drops.
+    pub fn set_edge_triggered(&mut self, irq: u32, edge: bool) {
+        if let Some(edge_triggered) = self.edge_triggered.get_mut(irq as usize) {
+            *edge_triggered = edge;
+        }
+    }
+
+    /// Per-source interrupt counters, indexed by id.
+    pub fn stats(&self) -> &[IrqStats] {
+        &self.stats
+    }
+
+    /// Whether `irq` is pending, without claiming it.
+    pub fn is_pending(&self, irq: u32) -> bool {
+        let index = (irq / 32) as usize;
+        let offset = irq % 32;
+        self.pending
+            .get(index)
+            .is_some_and(|pending| pending & (1 << offset) != 0)
+    }
+
+    /// Asserts the `irq` line out of band of the bus clock aggregation.
+    pub fn raise(&mut self, irq: u32) {
+        self.irq(irq, true);
+    }
+
+    /// Deasserts the `irq` line out of band of the bus clock aggregation.
+    pub fn lower(&mut self, irq: u32) {
+        self.irq(irq, false);
     }
 
     pub(crate) fn irq(&mut self, irq: u32, enable: bool) {
+        if irq == 0 || irq as usize >= self.sources() {
+            return;
+        }
         let index = (irq / 32) as usize;
         let offset = irq % 32;
         let pending = self.pending[index];
-        if enable {
+        let rising = enable && !self.lines[irq as usize];
+        self.lines[irq as usize] = enable;
+        if rising {
+            self.stats[irq as usize].raised += 1;
+        }
+        if self.edge_triggered[irq as usize] {
+            if rising {
+                self.pending[index] |= 1 << offset;
+            }
+        } else if enable {
             self.pending[index] |= 1 << offset;
         } else {
             self.pending[index] &= !(1 << offset);
@@ -95,70 +278,131 @@ impl Plic {
             self.update = false;
             // HART_COUNT = 1
             // for context in 0..HART_COUNT {}
-            return Some(self.highest_irq(0) != 0);
+            return Some(self.highest_irq(0).0 != 0);
         }
         None
     }
 
+    /// The (id, priority) a claim from `context` would return right now,
+    /// without claiming it.
+    pub fn pending_summary(&self, context: usize) -> Option<(u32, u32)> {
+        if context >= self.contexts() {
+            return None;
+        }
+        Some(self.highest_irq(context)).filter(|&(irq, _)| irq != 0)
+    }
+
+    // completions for interrupts this context hasn't claimed are ignored
     fn complete(&mut self, context: usize, irq: u32) {
-        self.claimed[context / 2].at_mut(context % 2)[irq as usize] = false;
+        let claimed = self.claimed[context / 2].at_mut(context % 2);
+        if let Some(claimed) = claimed.get_mut(irq as usize)
+            && *claimed
+        {
+            *claimed = false;
+            self.update = true;
+        }
     }
 
     fn claim(&mut self, context: usize) -> u32 {
-        let irq = self.highest_irq(context);
-        let index = (irq / 8) as usize;
-        let offset = irq % 8;
+        let (irq, _) = self.highest_irq(context);
+        if irq != 0 {
+            self.stats[irq as usize].claimed += 1;
+        }
+        let index = (irq / 32) as usize;
+        let offset = irq % 32;
         self.pending[index] &= !(1 << offset);
         self.claimed[context / 2].at_mut(context % 2)[irq as usize] = true;
         irq
     }
 
-    fn highest_irq(&mut self, context: usize) -> u32 {
+    // Priority 0 never fires since it can't exceed any threshold. Ids are
+    // scanned in ascending order and only a strictly higher priority replaces
+    // the current pick, so ties go to the lowest id as the spec requires.
+    fn highest_irq(&self, context: usize) -> (u32, u32) {
         let mut irq: u32 = 0;
         let mut priority = 0;
-        for i in 1..INTERRUPT_COUNT {
+        for i in 1..self.sources() {
             let index = i / 32;
             let offset = i % 32;
             let hart = context / 2;
             let mode = context % 2;
             if self.enable[hart].at(mode)[index] & (1 << offset) != 0
                 && self.pending[index] & (1 << offset) != 0
-                && !self.claimed[hart].at(mode)[index]
+                && !self.claimed[hart].at(mode)[i]
                 && self.priorities[i] > *self.threshold[hart].at(mode)
+                // strict so an equal priority keeps the lower id
                 && self.priorities[i] > priority
             {
                 irq = i as u32;
                 priority = self.priorities[i];
             }
         }
-        irq
+        (irq, priority)
     }
 }
 
+fn pack_bits(bits: &[bool]) -> impl Iterator<Item = u32> + '_ {
+    bits.chunks(32).map(|chunk| {
+        chunk
+            .iter()
+            .enumerate()
+            .fold(0, |word, (i, &bit)| word | (bit as u32) << i)
+    })
+}
+
+fn unpack_bits(words: &mut impl Iterator<Item = u32>, bits: &mut [bool]) -> Option<()> {
+    for chunk in bits.chunks_mut(32) {
+        let word = words.next()?;
+        for (i, bit) in chunk.iter_mut().enumerate() {
+            *bit = word & (1 << i) != 0;
+        }
+    }
+    Some(())
+}
+
 impl Device for Plic {
+    fn name(&self) -> &str {
+        "plic"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        PLIC_START..PLIC_END + 1
+    }
+
     fn clk(&mut self, _irq: &mut Irq) {}
 
+    fn reset(&mut self) {
+        Plic::reset(self);
+    }
+
     fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
         if size != Size::_4 {
-            return Err(Exception::BusException);
+            return Err(Exception::LoadAccessFault { address, size });
         }
         match address {
-            PLIC_SOURCE_PRIORITY_START..=PLIC_SOURCE_PRIORITY_END => {
-                Ok(self.priorities[(address - PLIC_SOURCE_PRIORITY_START) as usize] as u64)
-            }
+            PLIC_SOURCE_PRIORITY_START..=PLIC_SOURCE_PRIORITY_END => Ok(self
+                .priorities
+                .get(((address - PLIC_START) / 4) as usize)
+                .map_or(0, |&priority| priority as u64)),
             PLIC_PENDING_START..=PLIC_PENDING_END => {
-                Ok(self.pending[(address - PLIC_PENDING_START) as usize] as u64)
+                Ok(self.pending[((address - PLIC_PENDING_START) / 4) as usize] as u64)
             }
             PLIC_SOURCE_ENABLE_START..=PLIC_SOURCE_ENABLE_END => {
                 let offset = (address - PLIC_SOURCE_ENABLE_START) as usize;
                 let context = offset / 0x80;
-                let item = offset % 0x80;
+                let item = offset % 0x80 / 4;
+                if context >= self.contexts() {
+                    return Err(Exception::LoadAccessFault { address, size });
+                }
                 Ok(self.enable[context / 2].at(context % 2)[item] as u64)
             }
             PLIC_THRESHOLD_CLIAM_COMPLETE_START..=PLIC_THRESHOLD_CLIAM_COMPLETE_END => {
                 let offset = (address - PLIC_THRESHOLD_CLIAM_COMPLETE_START) as usize;
                 let context = offset / 0x1000;
-                let item = offset % 0x1000;
+                let item = offset % 0x1000 / 4;
+                if context >= self.contexts() {
+                    return Err(Exception::LoadAccessFault { address, size });
+                }
                 match item {
                     // threshold
                     0 => Ok(*self.threshold[context / 2].at(context % 2) as u64),
@@ -168,41 +412,314 @@ impl Device for Plic {
                         self.update = true;
                         Ok(irq as u64)
                     }
-                    _ => Err(Exception::BusException),
+                    _ => Err(Exception::LoadAccessFault { address, size }),
                 }
             }
-            _ => Err(Exception::BusException),
+            _ => Err(Exception::LoadAccessFault { address, size }),
         }
     }
 
     fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
         if size != Size::_4 {
-            return Err(Exception::BusException);
+            return Err(Exception::StoreAccessFault { address, size });
         }
         match address {
             PLIC_SOURCE_PRIORITY_START..=PLIC_SOURCE_PRIORITY_END => {
-                self.priorities[(address - PLIC_SOURCE_PRIORITY_START) as usize] = data as u32
+                // priorities of unconfigured sources are hardwired to zero
+                let index = ((address - PLIC_START) / 4) as usize;
+                if let Some(priority) = self.priorities.get_mut(index) {
+                    *priority = data as u32;
+                }
             }
             PLIC_SOURCE_ENABLE_START..=PLIC_SOURCE_ENABLE_END => {
                 let offset = (address - PLIC_SOURCE_ENABLE_START) as usize;
                 let context = offset / 0x80;
-                let item = offset % 0x80;
+                let item = offset % 0x80 / 4;
+                if context >= self.contexts() {
+                    return Err(Exception::StoreAccessFault { address, size });
+                }
                 self.enable[context / 2].at_mut(context % 2)[item] = data as u32;
             }
             PLIC_THRESHOLD_CLIAM_COMPLETE_START..=PLIC_THRESHOLD_CLIAM_COMPLETE_END => {
                 let offset = (address - PLIC_THRESHOLD_CLIAM_COMPLETE_START) as usize;
                 let context = offset / 0x1000;
-                let item = offset % 0x1000;
+                let item = offset % 0x1000 / 4;
+                if context >= self.contexts() {
+                    return Err(Exception::StoreAccessFault { address, size });
+                }
                 match item {
                     // threshold
                     0 => *self.threshold[context / 2].at_mut(context % 2) = data as u32,
                     // complete
                     1 => self.complete(context, data as u32),
-                    _ => return Err(Exception::BusException),
+                    _ => return Err(Exception::StoreAccessFault { address, size }),
                 };
             }
-            _ => return Err(Exception::BusException),
+            _ => return Err(Exception::StoreAccessFault { address, size }),
         };
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLD: u32 = PLIC_THRESHOLD_CLIAM_COMPLETE_START;
+
+    fn enable_address(context: u32) -> u32 {
+        PLIC_SOURCE_ENABLE_START + context * 0x80
+    }
+
+    #[test]
+    fn harts_have_independent_contexts() {
+        let mut plic = Plic::with_harts(2);
+        plic.write(enable_address(2), Size::_4, 0b100).unwrap();
+        plic.write(THRESHOLD + 2 * 0x1000, Size::_4, 3).unwrap();
+
+        assert_eq!(plic.read(enable_address(0), Size::_4), Ok(0));
+        assert_eq!(plic.read(enable_address(2), Size::_4), Ok(0b100));
+        assert_eq!(plic.read(THRESHOLD, Size::_4), Ok(0));
+        assert_eq!(plic.read(THRESHOLD + 2 * 0x1000, Size::_4), Ok(3));
+        // a third hart doesn't exist
+        assert!(plic.read(enable_address(4), Size::_4).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_harts_is_rejected() {
+        Plic::with_harts(0);
+    }
+
+    #[test]
+    fn from_bytes_rejects_zero_harts() {
+        let mut bytes = Plic::with_harts(1).to_bytes();
+        bytes[..4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(Plic::from_bytes(&bytes).is_none());
+    }
+
+    // gives `irq` a priority and enables it for context 0
+    fn arm(plic: &mut Plic, irq: u32, priority: u32) {
+        if let Some(slot) = plic.priorities.get_mut(irq as usize) {
+            *slot = priority;
+        }
+        plic.enable[0].machine[(irq / 32) as usize] |= 1 << (irq % 32);
+    }
+
+    #[test]
+    fn sources_above_the_count_never_claim() {
+        let mut plic = Plic::with_config(1, 8);
+        arm(&mut plic, 10, 1);
+        plic.raise(10);
+        assert!(!plic.is_pending(10));
+        assert_eq!(plic.claim(0), 0);
+
+        arm(&mut plic, 7, 1);
+        plic.raise(7);
+        assert_eq!(plic.claim(0), 7);
+    }
+
+    #[test]
+    fn claim_clears_only_its_own_bit() {
+        let mut plic = Plic::new();
+        for irq in [3, 10, 12] {
+            arm(&mut plic, irq, 1);
+            plic.raise(irq);
+        }
+        plic.priorities[10] = 2;
+        assert_eq!(plic.claim(0), 10);
+        assert_eq!(plic.pending[0], (1 << 3) | (1 << 12));
+    }
+
+    #[test]
+    fn edge_source_stays_pending_after_pulse() {
+        let mut plic = Plic::new();
+        plic.set_edge_triggered(5, true);
+        arm(&mut plic, 5, 1);
+        plic.irq(5, true);
+        plic.irq(5, false);
+        assert!(plic.is_pending(5));
+        assert_eq!(plic.claim(0), 5);
+        assert!(!plic.is_pending(5));
+    }
+
+    #[test]
+    fn reset_quiets_check_interrupt() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 4, 1);
+        plic.raise(4);
+        assert_eq!(plic.claim(0), 4);
+        plic.reset();
+        assert_eq!(plic.check_interrupt(), None);
+        assert_eq!(plic.priorities[4], 0);
+        assert_eq!(plic.pending_summary(0), None);
+    }
+
+    #[test]
+    fn is_pending_follows_irq() {
+        let mut plic = Plic::new();
+        plic.irq(1, true);
+        plic.irq(33, true);
+        plic.irq(40, true);
+        plic.irq(40, false);
+        assert!(plic.is_pending(1));
+        assert!(plic.is_pending(33));
+        assert!(!plic.is_pending(40));
+        assert!(!plic.is_pending(2));
+        assert!(!plic.is_pending(5000));
+    }
+
+    #[test]
+    fn complete_ignores_unclaimed() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 6, 1);
+        plic.raise(6);
+        plic.update = false;
+        plic.complete(0, 6);
+        assert!(!plic.update);
+
+        assert_eq!(plic.claim(0), 6);
+        plic.raise(6);
+        // still in service until completed
+        assert_eq!(plic.claim(0), 0);
+        plic.complete(0, 6);
+        assert!(plic.update);
+        assert_eq!(plic.claim(0), 6);
+    }
+
+    #[test]
+    fn registers_decode_by_word() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 35, 1);
+        plic.raise(35);
+        assert_eq!(
+            plic.read(PLIC_PENDING_START + 4, Size::_4),
+            Ok(1 << (35 - 32))
+        );
+        assert_eq!(plic.read(PLIC_PENDING_START, Size::_4), Ok(0));
+
+        plic.write(THRESHOLD, Size::_4, 0).unwrap();
+        assert_eq!(plic.read(THRESHOLD + 4, Size::_4), Ok(35));
+        assert!(plic.claimed[0].machine[35]);
+        plic.write(THRESHOLD + 4, Size::_4, 35).unwrap();
+        assert!(!plic.claimed[0].machine[35]);
+        // nothing past claim/complete in the context's block
+        assert!(plic.read(THRESHOLD + 8, Size::_4).is_err());
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_id() {
+        let mut plic = Plic::new();
+        for irq in [9, 4, 20] {
+            arm(&mut plic, irq, 3);
+            plic.raise(irq);
+        }
+        assert_eq!(plic.claim(0), 4);
+        assert_eq!(plic.claim(0), 9);
+        assert_eq!(plic.claim(0), 20);
+    }
+
+    #[test]
+    fn priority_zero_never_fires() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 2, 0);
+        plic.raise(2);
+        assert_eq!(plic.claim(0), 0);
+    }
+
+    #[test]
+    fn pending_summary_predicts_claim() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 8, 2);
+        arm(&mut plic, 11, 5);
+        plic.raise(8);
+        plic.raise(11);
+        assert_eq!(plic.pending_summary(0), Some((11, 5)));
+        assert_eq!(plic.claim(0), 11);
+        assert_eq!(plic.pending_summary(0), Some((8, 2)));
+        assert_eq!(plic.claim(0), 8);
+        assert_eq!(plic.pending_summary(0), None);
+        assert_eq!(plic.pending_summary(2), None);
+    }
+
+    #[test]
+    fn raise_reports_an_interrupt() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 3, 2);
+        plic.threshold[0].machine = 1;
+        plic.raise(3);
+        assert_eq!(plic.check_interrupt(), Some(true));
+        plic.lower(3);
+        assert_eq!(plic.check_interrupt(), Some(false));
+
+        // at the threshold isn't above it
+        plic.threshold[0].machine = 2;
+        plic.raise(3);
+        assert_eq!(plic.check_interrupt(), Some(false));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut plic = Plic::with_config(2, 40);
+        plic.set_edge_triggered(7, true);
+        arm(&mut plic, 7, 1);
+        arm(&mut plic, 33, 4);
+        plic.enable[1].supervisor[1] = 0b10;
+        plic.threshold[1].supervisor = 2;
+        plic.raise(7);
+        plic.raise(33);
+        assert_eq!(plic.claim(0), 33);
+
+        let mut restored = Plic::from_bytes(&plic.to_bytes()).unwrap();
+        assert_eq!(restored.to_bytes(), plic.to_bytes());
+        assert!(restored.claimed[0].machine[33]);
+        assert_eq!(restored.claim(0), 7);
+        assert!(restored.edge_triggered[7]);
+        assert_eq!(*restored.threshold[1].at(1), 2);
+
+        let bytes = plic.to_bytes();
+        assert!(Plic::from_bytes(&bytes[..bytes.len() - 4]).is_none());
+    }
+
+    #[test]
+    fn stats_count_claims() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 12, 1);
+        for _ in 0..3 {
+            plic.raise(12);
+            assert_eq!(plic.claim(0), 12);
+            plic.complete(0, 12);
+            plic.lower(12);
+        }
+        assert_eq!(plic.stats()[12].claimed, 3);
+        assert_eq!(plic.stats()[12].raised, 3);
+    }
+
+    #[test]
+    fn clear_pending_keeps_enables() {
+        let mut plic = Plic::new();
+        arm(&mut plic, 3, 1);
+        arm(&mut plic, 40, 1);
+        plic.raise(3);
+        plic.raise(40);
+        plic.clear_pending();
+        assert_eq!(plic.claim(0), 0);
+        assert_eq!(plic.check_interrupt(), Some(false));
+        assert_eq!(plic.enable[0].machine[0], 1 << 3);
+        assert_eq!(plic.priorities[40], 1);
+    }
+
+    #[test]
+    fn largest_source_count_is_accepted() {
+        let mut plic = Plic::with_config(1, 1024);
+        arm(&mut plic, 1023, 1);
+        plic.raise(1023);
+        assert_eq!(plic.claim(0), 1023);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 1024 sources")]
+    fn too_many_sources_is_rejected() {
+        Plic::with_config(1, 1025);
+    }
+}
@@ -0,0 +1,194 @@
+use std::ops::Range;
+
+use crate::utils::{
+    Exception, Size,
+    channel::{Receiver, Sender, bounded},
+};
+
+use super::{Device, Irq};
+
+pub(crate) const PWM_SIZE: u32 = 0x100;
+
+const PWM_CTRL: u32 = 0x00;
+// in device clocks
+const PWM_PERIOD: u32 = 0x04;
+// clocks per period the output is high, from the start of the period
+const PWM_DUTY: u32 = 0x08;
+// position within the current period, read-only
+const PWM_COUNTER: u32 = 0x0c;
+
+const PWM_CTRL_ENABLE: u32 = 0b1;
+
+// periods the host hasn't received yet; newer ones are dropped past this
+const PWM_QUEUE_DEPTH: usize = 1024;
+
+/// One completed PWM period, sent to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmPeriod {
+    /// Clocks the output was high.
+    pub high: u32,
+    /// Length of the period in clocks.
+    pub period: u32,
+}
+
+impl PwmPeriod {
+    /// The average output level over the period, 0.0 to 1.0.
+    pub fn duty_cycle(&self) -> f64 {
+        self.high as f64 / self.period as f64
+    }
+}
+
+/// A single-channel PWM output counting in device clocks. The host observes the
+/// output through the returned receiver, one `PwmPeriod` per completed period.
+/// Periods are dropped while the host is 1024 behind, so the guest never
+/// waits on it.
+#[derive(Debug)]
+pub struct Pwm {
+    base: u32,
+    sender: Sender<PwmPeriod>,
+    ctrl: u32,
+    period: u32,
+    duty: u32,
+    counter: u32,
+    // clocks high so far in the current period
+    high: u32,
+}
+
+impl Pwm {
+    pub fn new(base: u32) -> (Pwm, Receiver<PwmPeriod>) {
+        let (sender, receiver) = bounded(PWM_QUEUE_DEPTH);
+        (
+            Pwm {
+                base,
+                sender,
+                ctrl: 0,
+                period: 0,
+                duty: 0,
+                counter: 0,
+                high: 0,
+            },
+            receiver,
+        )
+    }
+
+    // the output is low while disabled
+    fn level(&self) -> bool {
+        self.ctrl & PWM_CTRL_ENABLE != 0 && self.counter < self.duty
+    }
+}
+
+impl Device for Pwm {
+    fn name(&self) -> &str {
+        "pwm"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + PWM_SIZE
+    }
+
+    // a new period or duty takes effect at once, without waiting for the
+    // current period to end
+    fn clk(&mut self, _irq: &mut Irq) {
+        if self.ctrl & PWM_CTRL_ENABLE == 0 || self.period == 0 {
+            return;
+        }
+        self.high += self.level() as u32;
+        self.counter += 1;
+        if self.counter >= self.period {
+            let _ = self.sender.try_send(PwmPeriod {
+                high: self.high,
+                period: self.counter,
+            });
+            self.counter = 0;
+            self.high = 0;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.period = 0;
+        self.duty = 0;
+        self.counter = 0;
+        self.high = 0;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            PWM_CTRL => self.ctrl,
+            PWM_PERIOD => self.period,
+            PWM_DUTY => self.duty,
+            PWM_COUNTER => self.counter,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            PWM_CTRL => {
+                // disabling abandons the current period
+                if data & PWM_CTRL_ENABLE == 0 {
+                    self.counter = 0;
+                    self.high = 0;
+                }
+                self.ctrl = data;
+            }
+            PWM_PERIOD => self.period = data,
+            PWM_DUTY => self.duty = data,
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_6000;
+
+    fn configure(pwm: &mut Pwm, period: u32, duty: u32) {
+        pwm.write(BASE + PWM_PERIOD, Size::_4, period as u64)
+            .unwrap();
+        pwm.write(BASE + PWM_DUTY, Size::_4, duty as u64).unwrap();
+        pwm.write(BASE + PWM_CTRL, Size::_4, PWM_CTRL_ENABLE as u64)
+            .unwrap();
+    }
+
+    #[test]
+    fn quarter_duty_is_high_a_quarter_of_the_period() {
+        let (mut pwm, periods) = Pwm::new(BASE);
+        configure(&mut pwm, 8, 2);
+        let mut irq = Irq::new();
+        for _ in 0..8 {
+            pwm.clk(&mut irq);
+        }
+        let period = periods.recv().unwrap();
+        assert_eq!(period, PwmPeriod { high: 2, period: 8 });
+        assert_eq!(period.duty_cycle(), 0.25);
+        assert!(!periods.avaliable());
+    }
+
+    #[test]
+    fn periods_are_dropped_while_the_host_lags() {
+        let (mut pwm, periods) = Pwm::new(BASE);
+        configure(&mut pwm, 1, 1);
+        let mut irq = Irq::new();
+        for _ in 0..PWM_QUEUE_DEPTH + 10 {
+            pwm.clk(&mut irq);
+        }
+        let mut received = 0;
+        while periods.avaliable() {
+            periods.recv();
+            received += 1;
+        }
+        assert_eq!(received, PWM_QUEUE_DEPTH);
+    }
+}
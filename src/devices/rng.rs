@@ -0,0 +1,134 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    ops::Range,
+};
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+pub(crate) const RNG_SIZE: u32 = 0x100;
+
+const RNG_STATUS: u32 = 0x00;
+// a fresh random word per read
+const RNG_DATA: u32 = 0x04;
+
+// words are generated on demand, so one is always ready
+const RNG_STATUS_READY: u32 = 0b1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RngConfig {
+    pub base: u32,
+    /// Produce the same sequence on every run, e.g. for deterministic
+    /// replay. `None` seeds from the OS.
+    pub seed: Option<u64>,
+}
+
+impl RngConfig {
+    pub fn new(base: u32) -> RngConfig {
+        RngConfig { base, seed: None }
+    }
+}
+
+/// A random number generator. Not cryptographically secure, even when seeded
+/// from the OS.
+#[derive(Debug)]
+pub struct Rng {
+    base: u32,
+    seed: Option<u64>,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(config: &RngConfig) -> Rng {
+        Rng {
+            base: config.base,
+            seed: config.seed,
+            state: config.seed.unwrap_or_else(os_seed),
+        }
+    }
+
+    // splitmix64
+    fn next(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+}
+
+// std seeds each `RandomState` from the OS
+fn os_seed() -> u64 {
+    RandomState::new().hash_one(0u64)
+}
+
+impl Device for Rng {
+    fn name(&self) -> &str {
+        "rng"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + RNG_SIZE
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {}
+
+    // a seeded generator starts its sequence over
+    fn reset(&mut self) {
+        if let Some(seed) = self.seed {
+            self.state = seed;
+        }
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            RNG_STATUS => RNG_STATUS_READY,
+            RNG_DATA => self.next(),
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, _data: u64) -> Result<(), Exception> {
+        Err(Exception::StoreAccessFault { address, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_a000;
+
+    fn words(rng: &mut Rng) -> Vec<u64> {
+        (0..8)
+            .map(|_| rng.read(BASE + RNG_DATA, Size::_4).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn seeded_sequence_is_reproducible() {
+        let config = RngConfig {
+            seed: Some(42),
+            ..RngConfig::new(BASE)
+        };
+        let first = words(&mut Rng::new(&config));
+        assert_eq!(first, words(&mut Rng::new(&config)));
+        assert_ne!(first[0], first[1]);
+
+        let mut rng = Rng::new(&config);
+        words(&mut rng);
+        rng.reset();
+        assert_eq!(words(&mut rng), first);
+
+        let other = RngConfig {
+            seed: Some(43),
+            ..config
+        };
+        assert_ne!(words(&mut Rng::new(&other)), first);
+    }
+}
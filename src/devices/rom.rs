@@ -0,0 +1,77 @@
+use std::ops::Range;
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+/// Read-only memory holding a fixed image, e.g. boot code. Stores fault.
+#[derive(Debug)]
+pub struct Rom {
+    base: u32,
+    image: Vec<u8>,
+}
+
+impl Rom {
+    pub fn new(base: u32, image: Vec<u8>) -> Rom {
+        assert!(
+            base.checked_add(image.len() as u32).is_some(),
+            "ROM image runs past the end of the address space"
+        );
+        Rom { base, image }
+    }
+}
+
+impl Device for Rom {
+    fn name(&self) -> &str {
+        "rom"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + self.image.len() as u32
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {}
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if !size.is_aligned(address) {
+            return Err(Exception::LoadAddressMisaligned { address, size });
+        }
+        let offset = (address - self.base) as usize;
+        let len = size.bytes();
+        match self.image.get(offset..offset + len) {
+            Some(bytes) if len <= 8 => {
+                let mut data = [0; 8];
+                data[..len].copy_from_slice(bytes);
+                Ok(u64::from_le_bytes(data))
+            }
+            _ => Err(Exception::LoadAccessFault { address, size }),
+        }
+    }
+
+    fn write(&mut self, address: u32, size: Size, _data: u64) -> Result<(), Exception> {
+        Err(Exception::StoreAccessFault { address, size })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x2000_0000;
+
+    #[test]
+    fn reads_return_the_image_and_writes_fault() {
+        let mut rom = Rom::new(BASE, (1..=8).collect());
+        assert_eq!(rom.read(BASE, Size::_4), Ok(0x0403_0201));
+        assert_eq!(rom.read(BASE + 6, Size::_2), Ok(0x0807));
+        assert_eq!(rom.read(BASE, Size::_8), Ok(0x0807_0605_0403_0201));
+        assert_eq!(
+            rom.write(BASE, Size::_1, 0),
+            Err(Exception::StoreAccessFault {
+                address: BASE,
+                size: Size::_1,
+            })
+        );
+        assert_eq!(rom.read(BASE, Size::_1), Ok(1));
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use sdl2::{
+    AudioSubsystem, EventPump, JoystickSubsystem,
+    audio::{AudioQueue, AudioSpecDesired},
+    event::{Event, WindowEvent},
+    joystick::Joystick,
+    keyboard::Scancode,
+    mouse::MouseButton,
+    pixels::{Color, PixelMasks},
+    rect::Rect,
+    surface::Surface,
+    video::Window,
+};
+
+use crate::utils::{
+    channel::{Receiver, Sender, channel},
+    u32_to_u8,
+};
+
+use super::{framebuffer::PixelFormat, ysyx::YsyxEvent};
+
+// SDL objects can't leave the thread that initialized SDL, so the window,
+// audio and input all live on a frontend thread the ysyx device talks to
+
+// the framebuffer as last synced by the guest, shared with the frontend
+pub(crate) struct FrontBuffer {
+    pub(crate) pixels: Arc<Mutex<Vec<u32>>>,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) format: PixelFormat,
+}
+
+pub(crate) enum FrontendCommand {
+    // the front buffer was updated, only the given region if any
+    Frame(Option<Rect>),
+    AudioInit {
+        freq: u32,
+        channels: u32,
+        samples: u32,
+    },
+    Audio(Vec<i16>),
+}
+
+pub(crate) struct Frontend {
+    commands: Option<Sender<FrontendCommand>>,
+    // bytes still queued for playback, as last seen by the frontend
+    audio_queued: Arc<AtomicU32>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Frontend {
+    // fails if SDL can't open the window, including when another frontend
+    // already holds SDL, which only initializes once per process
+    pub(crate) fn spawn(events: Sender<YsyxEvent>, front: FrontBuffer) -> io::Result<Frontend> {
+        let (commands, receiver) = channel();
+        let (ready, started) = channel();
+        let audio_queued = Arc::new(AtomicU32::new(0));
+        let queued = audio_queued.clone();
+        let thread = thread::spawn(move || match Sdl::new(front) {
+            Ok(sdl) => {
+                ready.send(Ok(()));
+                sdl.run(receiver, events, queued);
+            }
+            Err(e) => ready.send(Err(e)),
+        });
+        if let Err(e) = started
+            .recv()
+            .unwrap_or_else(|| Err("frontend thread exited".to_string()))
+        {
+            let _ = thread.join();
+            return Err(io::Error::other(e));
+        }
+        Ok(Frontend {
+            commands: Some(commands),
+            audio_queued,
+            thread: Some(thread),
+        })
+    }
+
+    // never blocks, the frontend catches up on its own time
+    pub(crate) fn send(&self, command: FrontendCommand) {
+        if let Some(commands) = &self.commands {
+            commands.send(command);
+        }
+    }
+
+    pub(crate) fn audio_queued(&self) -> u32 {
+        self.audio_queued.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Frontend {
+    fn drop(&mut self) {
+        // closing the command channel stops the thread
+        self.commands.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Sdl {
+    window: Window,
+    event_pump: EventPump,
+    // None when the host has no audio, which leaves the guest silent
+    audio: Option<AudioSubsystem>,
+    audio_queue: Option<AudioQueue<i16>>,
+    // None when the host has no joystick support; input still works
+    joystick: Option<JoystickSubsystem>,
+    // opened as SDL reports them, including those present at startup; each
+    // reports events until dropped
+    joysticks: Vec<Joystick>,
+    front: FrontBuffer,
+    // private copy of the front buffer, so the guest is never kept waiting
+    // on the lock while a blit runs; also redrawn after the window is
+    // resized or uncovered
+    pixels: Vec<u32>,
+    has_frame: bool,
+}
+
+impl Sdl {
+    fn new(front: FrontBuffer) -> Result<Sdl, String> {
+        let sdl_ctx = sdl2::init()?;
+        let video = sdl_ctx.video()?;
+        let window = video
+            .window("ysyx", 1024, 768)
+            .position_centered()
+            .resizable()
+            .opengl()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let event_pump = sdl_ctx.event_pump()?;
+        let audio = sdl_ctx.audio().ok();
+        let joystick = sdl_ctx.joystick().ok();
+        let pixels = front.pixels.lock().unwrap().clone();
+        Ok(Sdl {
+            window,
+            event_pump,
+            audio,
+            audio_queue: None,
+            joystick,
+            joysticks: Vec::new(),
+            pixels,
+            front,
+            has_frame: false,
+        })
+    }
+
+    fn run(
+        mut self,
+        commands: Receiver<FrontendCommand>,
+        events: Sender<YsyxEvent>,
+        audio_queued: Arc<AtomicU32>,
+    ) {
+        loop {
+            let polled: Vec<Event> = self.event_pump.poll_iter().collect();
+            for event in polled {
+                match event {
+                    Event::Window {
+                        win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                        ..
+                    } => {
+                        if self.has_frame {
+                            self.present(None);
+                        }
+                    }
+                    Event::JoyDeviceAdded { which, .. } => {
+                        if let Some(Ok(joystick)) =
+                            self.joystick.as_ref().map(|joystick| joystick.open(which))
+                        {
+                            self.joysticks.push(joystick);
+                        }
+                    }
+                    event => {
+                        if let Some(event) = translate_event(&event) {
+                            events.send(event);
+                        }
+                    }
+                }
+            }
+
+            while commands.avaliable() {
+                match commands.recv() {
+                    Some(FrontendCommand::Frame(region)) => {
+                        self.pixels
+                            .copy_from_slice(&self.front.pixels.lock().unwrap());
+                        self.has_frame = true;
+                        self.present(region);
+                    }
+                    Some(FrontendCommand::AudioInit {
+                        freq,
+                        channels,
+                        samples,
+                    }) => self.audio_init(freq, channels, samples),
+                    Some(FrontendCommand::Audio(samples)) => {
+                        if let Some(queue) = &self.audio_queue {
+                            let _ = queue.queue_audio(&samples);
+                        }
+                    }
+                    None => return,
+                }
+            }
+            if commands.sender_count() == 0 {
+                return;
+            }
+
+            let queued = self.audio_queue.as_ref().map_or(0, |queue| queue.size());
+            audio_queued.store(queued, Ordering::Relaxed);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn audio_init(&mut self, freq: u32, channels: u32, samples: u32) {
+        let spec = AudioSpecDesired {
+            freq: Some(freq as i32),
+            channels: Some(channels as u8),
+            samples: Some(samples as u16),
+        };
+        // an unsupported configuration leaves audio off rather than
+        // bringing the machine down
+        self.audio_queue = self
+            .audio
+            .as_ref()
+            .and_then(|audio| audio.open_queue(None, &spec).ok());
+        if let Some(queue) = &self.audio_queue {
+            queue.resume();
+        }
+    }
+
+    fn present(&mut self, region: Option<Rect>) {
+        let (width, height) = (self.front.width as u32, self.front.height as u32);
+        let surface = Surface::from_data_pixelmasks(
+            u32_to_u8(&mut self.pixels),
+            width,
+            height,
+            (self.front.width * self.front.format.bytes()) as u32,
+            &masks(self.front.format),
+        )
+        .unwrap();
+        let mut w_surface = self.window.surface(&self.event_pump).unwrap();
+        let dst = fit(width, height, w_surface.width(), w_surface.height());
+        match region {
+            Some(region) => {
+                // the part of `dst` the region scales to, rounded outwards
+                let (width, height) = (width as u64, height as u64);
+                let (dst_width, dst_height) = (dst.width() as u64, dst.height() as u64);
+                let x0 = region.x() as u64 * dst_width / width;
+                let y0 = region.y() as u64 * dst_height / height;
+                let x1 = (region.right() as u64 * dst_width).div_ceil(width);
+                let y1 = (region.bottom() as u64 * dst_height).div_ceil(height);
+                let target = Rect::new(
+                    dst.x() + x0 as i32,
+                    dst.y() + y0 as i32,
+                    (x1 - x0).max(1) as u32,
+                    (y1 - y0).max(1) as u32,
+                );
+                surface.blit_scaled(region, &mut w_surface, target).unwrap();
+            }
+            None => {
+                // clear the letterbox bars left over from the previous size
+                w_surface.fill_rect(None, Color::BLACK).unwrap();
+                surface.blit_scaled(None, &mut w_surface, dst).unwrap();
+            }
+        }
+        w_surface.finish().unwrap();
+    }
+}
+
+fn translate_event(event: &Event) -> Option<YsyxEvent> {
+    match *event {
+        Event::Quit { .. } => Some(YsyxEvent::Quit),
+        Event::KeyDown {
+            scancode: Some(scancode),
+            ..
+        } => keycode_to_amkey(scancode).map(|code| YsyxEvent::Key { code, down: true }),
+        Event::KeyUp {
+            scancode: Some(scancode),
+            ..
+        } => keycode_to_amkey(scancode).map(|code| YsyxEvent::Key { code, down: false }),
+        Event::MouseMotion { xrel, yrel, .. } => {
+            Some(YsyxEvent::MouseMotion { dx: xrel, dy: yrel })
+        }
+        Event::MouseButtonDown { mouse_btn, .. } => {
+            mouse_button(mouse_btn).map(|button| YsyxEvent::MouseButton { button, down: true })
+        }
+        Event::MouseButtonUp { mouse_btn, .. } => {
+            mouse_button(mouse_btn).map(|button| YsyxEvent::MouseButton {
+                button,
+                down: false,
+            })
+        }
+        Event::JoyButtonDown { button_idx, .. } => Some(YsyxEvent::GamepadButton {
+            button: button_idx,
+            down: true,
+        }),
+        Event::JoyButtonUp { button_idx, .. } => Some(YsyxEvent::GamepadButton {
+            button: button_idx,
+            down: false,
+        }),
+        Event::JoyAxisMotion {
+            axis_idx, value, ..
+        } => Some(YsyxEvent::GamepadAxis {
+            axis: axis_idx,
+            value,
+        }),
+        _ => None,
+    }
+}
+
+fn mouse_button(button: MouseButton) -> Option<u8> {
+    match button {
+        MouseButton::Left => Some(0),
+        MouseButton::Right => Some(1),
+        MouseButton::Middle => Some(2),
+        _ => None,
+    }
+}
+
+fn keycode_to_amkey(scancode: Scancode) -> Option<u32> {
+    match scancode {
+        Scancode::Escape => Some(1),
+        Scancode::F1 => Some(2),
+        Scancode::F2 => Some(3),
+        Scancode::F3 => Some(4),
+        Scancode::F4 => Some(5),
+        Scancode::F5 => Some(6),
+        Scancode::F6 => Some(7),
+        Scancode::F7 => Some(8),
+        Scancode::F8 => Some(9),
+        Scancode::F9 => Some(10),
+        Scancode::F10 => Some(11),
+        Scancode::F11 => Some(12),
+        Scancode::F12 => Some(13),
+        Scancode::Grave => Some(14),
+        Scancode::Num1 => Some(15),
+        Scancode::Num2 => Some(16),
+        Scancode::Num3 => Some(17),
+        Scancode::Num4 => Some(18),
+        Scancode::Num5 => Some(19),
+        Scancode::Num6 => Some(20),
+        Scancode::Num7 => Some(21),
+        Scancode::Num8 => Some(22),
+        Scancode::Num9 => Some(23),
+        Scancode::Num0 => Some(24),
+        Scancode::Minus => Some(25),
+        Scancode::Equals => Some(26),
+        Scancode::Backspace => Some(27),
+        Scancode::Tab => Some(28),
+        Scancode::Q => Some(29),
+        Scancode::W => Some(30),
+        Scancode::E => Some(31),
+        Scancode::R => Some(32),
+        Scancode::T => Some(33),
+        Scancode::Y => Some(34),
+        Scancode::U => Some(35),
+        Scancode::I => Some(36),
+        Scancode::O => Some(37),
+        Scancode::P => Some(38),
+        Scancode::LeftBracket => Some(39),
+        Scancode::RightBracket => Some(40),
+        Scancode::Backslash => Some(41),
+        Scancode::CapsLock => Some(42),
+        Scancode::A => Some(43),
+        Scancode::S => Some(44),
+        Scancode::D => Some(45),
+        Scancode::F => Some(46),
+        Scancode::G => Some(47),
+        Scancode::H => Some(48),
+        Scancode::J => Some(49),
+        Scancode::K => Some(50),
+        Scancode::L => Some(51),
+        Scancode::Semicolon => Some(52),
+        Scancode::Apostrophe => Some(53),
+        Scancode::Return => Some(54),
+        Scancode::LShift => Some(55),
+        Scancode::Z => Some(56),
+        Scancode::X => Some(57),
+        Scancode::C => Some(58),
+        Scancode::V => Some(59),
+        Scancode::B => Some(60),
+        Scancode::N => Some(61),
+        Scancode::M => Some(62),
+        Scancode::Comma => Some(63),
+        Scancode::Period => Some(64),
+        Scancode::Slash => Some(65),
+        Scancode::RShift => Some(66),
+        Scancode::LCtrl => Some(67),
+        Scancode::Application => Some(68),
+        Scancode::LAlt => Some(69),
+        Scancode::Space => Some(70),
+        Scancode::RAlt => Some(71),
+        Scancode::RCtrl => Some(72),
+        Scancode::Up => Some(73),
+        Scancode::Down => Some(74),
+        Scancode::Left => Some(75),
+        Scancode::Right => Some(76),
+        Scancode::Insert => Some(77),
+        Scancode::Delete => Some(78),
+        Scancode::Home => Some(79),
+        Scancode::End => Some(80),
+        Scancode::PageUp => Some(81),
+        Scancode::PageDown => Some(82),
+        // not in abstract-machine's key list, numbered on from PAGEDOWN
+        Scancode::KpEnter => Some(83),
+        Scancode::Kp0 => Some(84),
+        Scancode::Kp1 => Some(85),
+        Scancode::Kp2 => Some(86),
+        Scancode::Kp3 => Some(87),
+        Scancode::Kp4 => Some(88),
+        Scancode::Kp5 => Some(89),
+        Scancode::Kp6 => Some(90),
+        Scancode::Kp7 => Some(91),
+        Scancode::Kp8 => Some(92),
+        Scancode::Kp9 => Some(93),
+        Scancode::KpPeriod => Some(94),
+        Scancode::KpPlus => Some(95),
+        Scancode::KpMinus => Some(96),
+        Scancode::KpMultiply => Some(97),
+        Scancode::KpDivide => Some(98),
+        Scancode::NumLockClear => Some(99),
+        Scancode::PrintScreen => Some(100),
+        Scancode::ScrollLock => Some(101),
+        Scancode::Pause => Some(102),
+        Scancode::LGui => Some(103),
+        Scancode::RGui => Some(104),
+        _ => None,
+    }
+}
+
+fn masks(format: PixelFormat) -> PixelMasks {
+    match format {
+        PixelFormat::Xrgb8888 => PixelMasks {
+            bpp: 32,
+            rmask: 0x00ff0000,
+            gmask: 0x0000ff00,
+            bmask: 0x000000ff,
+            amask: 0x00000000,
+        },
+        PixelFormat::Rgb565 => PixelMasks {
+            bpp: 16,
+            rmask: 0xf800,
+            gmask: 0x07e0,
+            bmask: 0x001f,
+            amask: 0x0000,
+        },
+    }
+}
+
+// largest rect with the framebuffer's aspect ratio centered in the window
+fn fit(width: u32, height: u32, window_width: u32, window_height: u32) -> Rect {
+    let (width, height, window_width, window_height) = (
+        width as u64,
+        height as u64,
+        window_width as u64,
+        window_height as u64,
+    );
+    let (w, h) = if window_width * height <= window_height * width {
+        (window_width, window_width * height / width)
+    } else {
+        (window_height * width / height, window_height)
+    };
+    Rect::new(
+        ((window_width - w) / 2) as i32,
+        ((window_height - h) / 2) as i32,
+        w.max(1) as u32,
+        h.max(1) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio_after_a_resize() {
+        // wider window: bars left and right
+        assert_eq!(fit(400, 300, 1600, 900), Rect::new(200, 0, 1200, 900));
+        // taller window: bars top and bottom
+        assert_eq!(fit(400, 300, 800, 1000), Rect::new(0, 200, 800, 600));
+        // never collapses to nothing
+        assert_eq!(fit(400, 300, 1, 1), Rect::new(0, 0, 1, 1));
+    }
+
+    #[test]
+    fn keypad_and_extra_keys_map() {
+        for scancode in [
+            Scancode::Kp0,
+            Scancode::KpEnter,
+            Scancode::PrintScreen,
+            Scancode::Pause,
+            Scancode::LGui,
+        ] {
+            assert!(keycode_to_amkey(scancode).is_some(), "{scancode:?}");
+        }
+        assert_eq!(keycode_to_amkey(Scancode::Kp5), Some(89));
+    }
+}
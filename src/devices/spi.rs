@@ -0,0 +1,202 @@
+use std::ops::Range;
+
+use crate::utils::{Exception, Size};
+
+use super::{Device, Irq};
+
+pub(crate) const SPI_SIZE: u32 = 0x100;
+const SPI_CS_LINES: usize = 8;
+
+// write: shift a byte out to the selected slaves; read: the byte shifted in
+const SPI_DATA: u32 = 0x00;
+// one bit per chip select line, 1 = asserted
+const SPI_CS: u32 = 0x04;
+const SPI_STATUS: u32 = 0x08;
+const SPI_CTRL: u32 = 0x0c;
+
+// a received byte hasn't been read from DATA yet
+const SPI_STATUS_RX_VALID: u32 = 0b01;
+// interrupt while RX_VALID is set
+const SPI_CTRL_RX_IE: u32 = 0b01;
+
+// MISO floats high with nothing driving it
+const SPI_IDLE: u8 = 0xFF;
+
+/// A device on the SPI bus, e.g. a flash chip or a display controller.
+pub trait SpiSlave: std::fmt::Debug {
+    /// Exchanges one byte while selected: `mosi` is shifted in, the returned
+    /// byte shifted out.
+    fn transfer(&mut self, mosi: u8) -> u8;
+
+    /// Called when the slave's chip select line is asserted or released,
+    /// which usually frames a command.
+    fn select(&mut self, _selected: bool) {}
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SpiConfig {
+    pub base: u32,
+    pub interrupt_id: u32,
+}
+
+impl SpiConfig {
+    pub fn new(base: u32, interrupt_id: u32) -> SpiConfig {
+        SpiConfig { base, interrupt_id }
+    }
+}
+
+/// An SPI controller with 8 chip select lines. Transfers complete immediately;
+/// attach slaves with `attach`.
+#[derive(Debug)]
+pub struct Spi {
+    base: u32,
+    interrupt_id: u32,
+    slaves: Vec<Option<Box<dyn SpiSlave>>>,
+    cs: u32,
+    rx: u8,
+    status: u32,
+    ctrl: u32,
+}
+
+impl Spi {
+    pub fn new(config: &SpiConfig) -> Spi {
+        Spi {
+            base: config.base,
+            interrupt_id: config.interrupt_id,
+            slaves: (0..SPI_CS_LINES).map(|_| None).collect(),
+            cs: 0,
+            rx: SPI_IDLE,
+            status: 0,
+            ctrl: 0,
+        }
+    }
+
+    /// Connects `slave` to chip select `line`, 0 to 7, replacing any slave
+    /// already there.
+    pub fn attach(&mut self, line: usize, slave: Box<dyn SpiSlave>) {
+        assert!(line < SPI_CS_LINES, "the SPI controller has 8 chip selects");
+        self.slaves[line] = Some(slave);
+    }
+
+    fn set_cs(&mut self, cs: u32) {
+        let changed = self.cs ^ cs;
+        self.cs = cs;
+        for (line, slave) in self.slaves.iter_mut().enumerate() {
+            if let Some(slave) = slave
+                && changed & (1 << line) != 0
+            {
+                slave.select(cs & (1 << line) != 0);
+            }
+        }
+    }
+
+    // every selected slave sees the byte; with several selected, the lowest
+    // line drives MISO
+    fn transfer(&mut self, mosi: u8) -> u8 {
+        let mut miso = None;
+        for (line, slave) in self.slaves.iter_mut().enumerate() {
+            if let Some(slave) = slave
+                && self.cs & (1 << line) != 0
+            {
+                let byte = slave.transfer(mosi);
+                miso.get_or_insert(byte);
+            }
+        }
+        miso.unwrap_or(SPI_IDLE)
+    }
+}
+
+impl Device for Spi {
+    fn name(&self) -> &str {
+        "spi"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + SPI_SIZE
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        irq.irq(
+            self.interrupt_id,
+            self.ctrl & SPI_CTRL_RX_IE != 0 && self.status & SPI_STATUS_RX_VALID != 0,
+        );
+    }
+
+    fn reset(&mut self) {
+        self.set_cs(0);
+        self.rx = SPI_IDLE;
+        self.status = 0;
+        self.ctrl = 0;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            SPI_DATA => {
+                self.status &= !SPI_STATUS_RX_VALID;
+                self.rx as u32
+            }
+            SPI_CS => self.cs,
+            SPI_STATUS => self.status,
+            SPI_CTRL => self.ctrl,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            SPI_DATA => {
+                self.rx = self.transfer(data as u8);
+                self.status |= SPI_STATUS_RX_VALID;
+            }
+            SPI_CS => self.set_cs(data & ((1 << SPI_CS_LINES) - 1)),
+            SPI_CTRL => self.ctrl = data,
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_4000;
+
+    // MISO echoes MOSI
+    #[derive(Debug)]
+    struct Loopback;
+
+    impl SpiSlave for Loopback {
+        fn transfer(&mut self, mosi: u8) -> u8 {
+            mosi
+        }
+    }
+
+    #[test]
+    fn loopback_slave_echoes_bytes() {
+        let mut spi = Spi::new(&SpiConfig::new(BASE, 6));
+        spi.attach(2, Box::new(Loopback));
+        spi.write(BASE + SPI_DATA, Size::_4, 0x5a).unwrap();
+        // nothing selected
+        assert_eq!(spi.read(BASE + SPI_DATA, Size::_4), Ok(SPI_IDLE as u64));
+
+        spi.write(BASE + SPI_CS, Size::_4, 1 << 2).unwrap();
+        for byte in [0x00, 0xa5, 0x3c] {
+            spi.write(BASE + SPI_DATA, Size::_4, byte).unwrap();
+            assert_eq!(
+                spi.read(BASE + SPI_STATUS, Size::_4),
+                Ok(SPI_STATUS_RX_VALID as u64)
+            );
+            assert_eq!(spi.read(BASE + SPI_DATA, Size::_4), Ok(byte));
+            assert_eq!(spi.read(BASE + SPI_STATUS, Size::_4), Ok(0));
+        }
+    }
+}
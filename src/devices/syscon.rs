@@ -0,0 +1,97 @@
+use std::ops::Range;
+
+use crate::utils::{
+    Exception, Size,
+    channel::{Receiver, Sender, channel},
+};
+
+use super::{Device, Irq};
+
+pub(crate) const SYSCON_SIZE: u32 = 0x1000;
+
+// as QEMU's sifive_test: the low half of a word written to offset 0 selects
+// the action, the high half is the exit code for FAIL
+const SYSCON_FAIL: u32 = 0x3333;
+const SYSCON_PASS: u32 = 0x5555;
+const SYSCON_RESET: u32 = 0x7777;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysconCommand {
+    /// The guest powered off with this exit code, 0 when it passed.
+    Poweroff(u16),
+    /// The guest asked for a system reset, e.g. with `Bus::reset`.
+    Reset,
+}
+
+/// A system controller compatible with QEMU's sifive_test, through which the
+/// guest powers off or resets the machine. Requests come out of the returned
+/// receiver; other values written are ignored.
+#[derive(Debug)]
+pub struct Syscon {
+    base: u32,
+    sender: Sender<SysconCommand>,
+}
+
+impl Syscon {
+    pub fn new(base: u32) -> (Syscon, Receiver<SysconCommand>) {
+        let (sender, receiver) = channel();
+        (Syscon { base, sender }, receiver)
+    }
+}
+
+impl Device for Syscon {
+    fn name(&self) -> &str {
+        "syscon"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + SYSCON_SIZE
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {}
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 || address != self.base {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        Ok(0)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 || address != self.base {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match data & 0xffff {
+            SYSCON_PASS => self.sender.send(SysconCommand::Poweroff(0)),
+            SYSCON_FAIL => self
+                .sender
+                .send(SysconCommand::Poweroff((data >> 16) as u16)),
+            SYSCON_RESET => self.sender.send(SysconCommand::Reset),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x0010_0000;
+
+    #[test]
+    fn magic_values_emit_commands() {
+        let (mut syscon, commands) = Syscon::new(BASE);
+        syscon
+            .write(BASE, Size::_4, (7 << 16 | SYSCON_FAIL) as u64)
+            .unwrap();
+        assert_eq!(commands.recv(), Some(SysconCommand::Poweroff(7)));
+        syscon.write(BASE, Size::_4, SYSCON_PASS as u64).unwrap();
+        assert_eq!(commands.recv(), Some(SysconCommand::Poweroff(0)));
+        syscon.write(BASE, Size::_4, SYSCON_RESET as u64).unwrap();
+        assert_eq!(commands.recv(), Some(SysconCommand::Reset));
+        syscon.write(BASE, Size::_4, 0x1234).unwrap();
+        assert!(!commands.avaliable());
+    }
+}
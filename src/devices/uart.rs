@@ -1,3 +1,5 @@
+use std::{collections::VecDeque, ops::Range};
+
 use crate::utils::{
     Exception,
     channel::{Receiver, Sender, channel},
@@ -5,51 +7,115 @@ use crate::utils::{
 
 use super::{Device, Irq, Size};
 
-// NS16550A
+// NS16550A, register addresses are offsets from the UART's base
 
 pub(crate) const UART_START: u32 = 0x10000000;
-pub(crate) const UART_END: u32 = UART_START + 8 - 1;
+pub(crate) const UART_SIZE: u32 = 8;
 pub(crate) const INTERRUPT_ID: u32 = 1;
 
-const UART_RBR_DLL: u32 = UART_START;
-const UART_THR: u32 = UART_START;
+pub(crate) const UART_FIFO_DEPTH: usize = 16;
+// character times without RX activity before the timeout interrupt
+const UART_RX_TIMEOUT: u32 = 4;
+
+const UART_RBR_DLL: u32 = 0;
+const UART_THR: u32 = 0;
 
-const UART_IER_ILM: u32 = UART_START + 1;
+const UART_IER_ILM: u32 = 1;
 const UART_IER_RDI: u8 = 0b00000001;
 const UART_IER_THRI: u8 = 0b00000010;
+const UART_IER_RLSI: u8 = 0b00000100;
+const UART_IER_MSI: u8 = 0b00001000;
 
-const UART_IIR: u32 = UART_START + 2;
-const UART_FCR: u32 = UART_START + 2;
+const UART_IIR: u32 = 2;
+const UART_FCR: u32 = 2;
 const UART_IIR_NO_INT: u8 = 0b00000001;
+const UART_IIR_MSI: u8 = 0b00000000;
 const UART_IIR_THRI: u8 = 0b00000010;
 const UART_IIR_RDI: u8 = 0b00000100;
+const UART_IIR_RLSI: u8 = 0b00000110;
+const UART_IIR_TIMEOUT: u8 = 0b00001100;
+const UART_IIR_FIFO_ENABLED: u8 = 0b11000000;
 const UART_FCR_ENABLE_FIFO: u8 = 0b00000001;
 const UART_FCR_CLEAR_RCVR: u8 = 0b00000010;
 const UART_FCR_CLEAR_XMIT: u8 = 0b00000100;
+const UART_FCR_TRIGGER: u8 = 0b11000000;
 
-const UART_LCR: u32 = UART_START + 3;
+const UART_LCR: u32 = 3;
+const UART_LCR_WLEN: u8 = 0b00000011;
+const UART_LCR_SBC: u8 = 0b01000000;
 const UART_LCR_DLAB: u8 = 0b10000000;
 
-const UART_MCR: u32 = UART_START + 4;
+const UART_MCR: u32 = 4;
+const UART_MCR_DTR: u8 = 0b00000001;
+const UART_MCR_RTS: u8 = 0b00000010;
+const UART_MCR_OUT1: u8 = 0b00000100;
 const UART_MCR_LOOP: u8 = 0b00010000;
+// auto flow control: CTS gates TX, and with RTS set RTS follows the RX FIFO
+const UART_MCR_AFE: u8 = 0b00100000;
 
-const UART_LSR: u32 = UART_START + 5;
+const UART_LSR: u32 = 5;
 const UART_LSR_DR: u8 = 0b00000001;
 const UART_LSR_OE: u8 = 0b00000010;
+const UART_LSR_FE: u8 = 0b00001000;
 const UART_LSR_BI: u8 = 0b00010000;
 const UART_LSR_THRE: u8 = 0b00100000;
 const UART_LSR_TEMT: u8 = 0b01000000;
 
-const UART_MSR: u32 = UART_START + 6;
+const UART_MSR: u32 = 6;
 const UART_MCR_OUT2: u8 = 0b00001000;
+const UART_MSR_DCTS: u8 = 0b00000001;
+const UART_MSR_DDSR: u8 = 0b00000010;
+const UART_MSR_TERI: u8 = 0b00000100;
+const UART_MSR_DDCD: u8 = 0b00001000;
+const UART_MSR_CTS: u8 = 0b00010000;
+const UART_MSR_DSR: u8 = 0b00100000;
+const UART_MSR_RI: u8 = 0b01000000;
+const UART_MSR_DCD: u8 = 0b10000000;
 
-const UART_SCR: u32 = UART_START + 7;
+const UART_SCR: u32 = 7;
+
+#[derive(Debug, Clone)]
+pub struct UartConfig {
+    pub base: u32,
+    /// PLIC source the UART interrupt is wired to.
+    pub interrupt_id: u32,
+    /// Depth of both the RX and TX FIFO; 16 on a 16550A, 64 on a 16750.
+    pub fifo_depth: usize,
+}
+
+impl UartConfig {
+    pub fn new(base: u32, interrupt_id: u32) -> UartConfig {
+        UartConfig {
+            base,
+            interrupt_id,
+            fifo_depth: UART_FIFO_DEPTH,
+        }
+    }
+}
 
 #[derive(Debug)]
-pub(crate) struct Uart {
+pub struct Uart {
+    base: u32,
+    interrupt_id: u32,
+    fifo_depth: usize,
     receiver: Receiver<u8>,
+    rx_fifo: VecDeque<u8>,
+    // clocks into the current character time
+    baud_ticks: u32,
+    // character times since the RX FIFO was last pushed or popped
+    rx_idle: u32,
     loop_sender: Sender<u8>,
     sender: Sender<u8>,
+    tx_fifo: VecDeque<u8>,
+    // the character currently being shifted out
+    tx_shift: Option<u8>,
+    // set by `inject_framing_error`, consumed by the next received byte
+    framing_error: bool,
+    // CTS as driven by the host side of the link
+    host_cts: bool,
+    // modem inputs as last sampled, and the delta bits latched since MSR was read
+    modem_inputs: u8,
+    msr_delta: u8,
     lcr: u8,
     dll: u8,
     dlm: u8,
@@ -62,22 +128,36 @@ pub(crate) struct Uart {
 }
 
 impl Uart {
-    pub(crate) fn new() -> (Uart, Sender<u8>, Receiver<u8>) {
+    pub(crate) fn new(config: &UartConfig) -> (Uart, Sender<u8>, Receiver<u8>) {
+        let fifo_depth = config.fifo_depth.max(1);
         let (recv_send, recv) = channel();
         let (send, send_recv) = channel();
         (
             Uart {
+                base: config.base,
+                interrupt_id: config.interrupt_id,
+                fifo_depth,
                 receiver: recv,
+                rx_fifo: VecDeque::with_capacity(fifo_depth),
+                baud_ticks: 0,
+                rx_idle: 0,
                 loop_sender: recv_send.clone(),
                 sender: send,
-                lcr: 0,
+                tx_fifo: VecDeque::with_capacity(fifo_depth),
+                tx_shift: None,
+                framing_error: false,
+                host_cts: true,
+                modem_inputs: UART_MSR_CTS,
+                msr_delta: 0,
+                // 8 data bits, so guests that never program LCR keep
+                // getting whole bytes
+                lcr: UART_LCR_WLEN,
                 dll: 0x0c,
                 dlm: 0,
                 ier: 0,
                 iir: UART_IIR_NO_INT,
                 mcr: UART_MCR_OUT2,
-                #[allow(clippy::eq_op)]
-                lsr: UART_LSR_TEMT | UART_LSR_TEMT,
+                lsr: UART_LSR_TEMT | UART_LSR_THRE,
                 scr: 0,
                 fcr: 0,
             },
@@ -85,60 +165,261 @@ impl Uart {
             send_recv,
         )
     }
-}
 
-impl Device for Uart {
-    fn clk(&mut self, irq: &mut Irq) {
-        // TODO: backoff counter?
-        if self.receiver.avaliable() {
-            self.lsr |= UART_LSR_DR;
-        }
+    /// Makes the next received byte arrive with a framing error, for testing
+    /// driver error handling.
+    pub fn inject_framing_error(&mut self) {
+        self.framing_error = true;
+    }
 
-        if self.lcr & UART_FCR_CLEAR_RCVR != 0 {
-            self.receiver.clear();
-            self.lsr &= !UART_LSR_DR & !UART_FCR_CLEAR_RCVR;
+    /// Drives the CTS input from the far end of the link. With auto flow
+    /// control enabled, transmission pauses while it is deasserted.
+    pub fn set_cts(&mut self, asserted: bool) {
+        self.host_cts = asserted;
+        self.update_modem_status();
+    }
+
+    // a disabled FIFO leaves the single 16450 holding register
+    fn fifo_capacity(&self) -> usize {
+        if self.fcr & UART_FCR_ENABLE_FIFO != 0 {
+            self.fifo_depth
+        } else {
+            1
         }
+    }
 
-        if self.lcr & UART_FCR_CLEAR_XMIT != 0 {
-            self.lcr &= !UART_FCR_CLEAR_XMIT;
-            self.lsr |= UART_LSR_TEMT | UART_LSR_THRE;
+    fn divisor(&self) -> u32 {
+        (((self.dlm as u32) << 8) | self.dll as u32).max(1)
+    }
+
+    fn character_time(&mut self) {
+        // one character arrives per character time; with no room left it is
+        // lost. Under auto flow control the far end holds off while RTS is
+        // deasserted, so the byte stays in the host channel.
+        self.rx_idle = self.rx_idle.saturating_add(1);
+        let held_off = self.mcr & UART_MCR_AFE != 0 && !self.rts();
+        if !held_off && self.receiver.avaliable() {
+            self.rx_idle = 0;
+            let data = self.receiver.recv().unwrap() & self.word_mask();
+            if self.rx_fifo.len() < self.fifo_capacity() {
+                self.rx_fifo.push_back(data);
+                self.lsr |= UART_LSR_DR;
+                if self.framing_error {
+                    self.framing_error = false;
+                    self.lsr |= UART_LSR_FE;
+                }
+            } else {
+                self.lsr |= UART_LSR_OE;
+            }
         }
 
-        let mut interrupts: u8 = 0;
-        if (self.ier & UART_IER_RDI) != 0 && (self.lsr & UART_LSR_DR) != 0 {
-            interrupts |= UART_IIR_RDI;
+        // a break holds TX in the spacing state, which the looped back
+        // receiver sees as a break condition
+        if self.lcr & UART_LCR_SBC != 0 {
+            if self.mcr & UART_MCR_LOOP != 0 {
+                self.lsr |= UART_LSR_BI;
+            }
+            self.tx_shift = None;
         }
 
-        if (self.ier & UART_IER_THRI) != 0 && (self.lsr & UART_LSR_TEMT) != 0 {
-            interrupts |= UART_IIR_THRI;
+        // and one character finishes transmitting
+        if let Some(data) = self.tx_shift.take() {
+            self.transmit(data);
         }
+        if self.mcr & UART_MCR_AFE == 0 || self.modem_status() & UART_MSR_CTS != 0 {
+            self.tx_shift = self.tx_fifo.pop_front();
+        }
+        self.update_tx_status();
+    }
 
-        if interrupts != 0 {
-            self.iir = UART_IIR_NO_INT;
-            irq.irq(INTERRUPT_ID, false);
+    fn transmit(&self, data: u8) {
+        let data = data & self.word_mask();
+        if self.mcr & UART_MCR_LOOP != 0 {
+            self.loop_sender.send(data);
         } else {
-            self.iir = interrupts;
-            irq.irq(INTERRUPT_ID, true);
+            self.sender.send(data);
+        }
+    }
+
+    /// Sends everything still waiting in the shift register and TX FIFO
+    /// right away instead of one character per character time.
+    pub fn flush(&mut self) {
+        while let Some(data) = self.tx_shift.take().or_else(|| self.tx_fifo.pop_front()) {
+            self.transmit(data);
+        }
+        self.update_tx_status();
+    }
+
+    // data bits per character, 5 to 8
+    fn word_mask(&self) -> u8 {
+        u8::MAX >> (3 - (self.lcr & UART_LCR_WLEN))
+    }
+
+    // modem inputs; in loopback they are wired to the MCR outputs
+    fn modem_status(&self) -> u8 {
+        if self.mcr & UART_MCR_LOOP == 0 {
+            return if self.host_cts { UART_MSR_CTS } else { 0 };
+        }
+        let mut msr = 0;
+        if self.rts() {
+            msr |= UART_MSR_CTS;
+        }
+        if self.mcr & UART_MCR_DTR != 0 {
+            msr |= UART_MSR_DSR;
         }
+        if self.mcr & UART_MCR_OUT1 != 0 {
+            msr |= UART_MSR_RI;
+        }
+        if self.mcr & UART_MCR_OUT2 != 0 {
+            msr |= UART_MSR_DCD;
+        }
+        msr
+    }
+
+    // auto-RTS drops RTS once the RX FIFO reaches its trigger level
+    fn rts(&self) -> bool {
+        self.mcr & UART_MCR_RTS != 0
+            && (self.mcr & UART_MCR_AFE == 0 || self.rx_fifo.len() < self.rx_trigger())
+    }
+
+    // latch changes of the modem inputs into the MSR delta bits
+    fn update_modem_status(&mut self) {
+        let inputs = self.modem_status();
+        let changed = inputs ^ self.modem_inputs;
+        if changed & UART_MSR_CTS != 0 {
+            self.msr_delta |= UART_MSR_DCTS;
+        }
+        if changed & UART_MSR_DSR != 0 {
+            self.msr_delta |= UART_MSR_DDSR;
+        }
+        // TERI only flags the trailing edge of RI
+        if changed & self.modem_inputs & UART_MSR_RI != 0 {
+            self.msr_delta |= UART_MSR_TERI;
+        }
+        if changed & UART_MSR_DCD != 0 {
+            self.msr_delta |= UART_MSR_DDCD;
+        }
+        self.modem_inputs = inputs;
+    }
 
-        if self.ier & UART_IER_THRI == 0 {
-            self.lsr |= UART_LSR_TEMT | UART_LSR_THRE;
+    // RX FIFO occupancy that raises the received data interrupt
+    fn rx_trigger(&self) -> usize {
+        if self.fcr & UART_FCR_ENABLE_FIFO == 0 {
+            return 1;
+        }
+        // 1, 4, 8 and 14 bytes on a 16-byte FIFO, scaled for other depths
+        let depth = self.fifo_depth;
+        match (self.fcr & UART_FCR_TRIGGER) >> 6 {
+            0 => 1,
+            1 => depth / 4,
+            2 => depth / 2,
+            _ => depth.saturating_sub(2),
         }
+        .max(1)
+    }
+
+    // THRE: nothing left in the FIFO, TEMT: the shift register is idle too
+    fn update_tx_status(&mut self) {
+        self.lsr &= !(UART_LSR_THRE | UART_LSR_TEMT);
+        if self.tx_fifo.is_empty() {
+            self.lsr |= UART_LSR_THRE;
+            if self.tx_shift.is_none() {
+                self.lsr |= UART_LSR_TEMT;
+            }
+        }
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl Device for Uart {
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + UART_SIZE
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        // the divisor latch sets how many clocks one character takes
+        self.baud_ticks += 1;
+        if self.baud_ticks >= self.divisor() {
+            self.baud_ticks = 0;
+            self.character_time();
+        }
+        self.update_modem_status();
+
+        // IIR reports only the highest priority pending source
+        let line_status = (self.ier & UART_IER_RLSI) != 0
+            && self.lsr & (UART_LSR_OE | UART_LSR_FE | UART_LSR_BI) != 0;
+        let data_ready = (self.ier & UART_IER_RDI) != 0 && self.rx_fifo.len() >= self.rx_trigger();
+        // data left below the trigger level with the line gone quiet
+        let timeout = (self.ier & UART_IER_RDI) != 0
+            && self.fcr & UART_FCR_ENABLE_FIFO != 0
+            && !self.rx_fifo.is_empty()
+            && self.rx_idle >= UART_RX_TIMEOUT;
+        let thr_empty = (self.ier & UART_IER_THRI) != 0 && (self.lsr & UART_LSR_THRE) != 0;
+        let modem_status = (self.ier & UART_IER_MSI) != 0 && self.msr_delta != 0;
+
+        self.iir = if line_status {
+            UART_IIR_RLSI
+        } else if data_ready {
+            UART_IIR_RDI
+        } else if timeout {
+            UART_IIR_TIMEOUT
+        } else if thr_empty {
+            UART_IIR_THRI
+        } else if modem_status {
+            UART_IIR_MSI
+        } else {
+            UART_IIR_NO_INT
+        };
+        irq.irq(self.interrupt_id, self.iir != UART_IIR_NO_INT);
+    }
+
+    // untransmitted bytes are dropped; bytes still in the host channel are
+    // received as usual
+    fn reset(&mut self) {
+        self.rx_fifo.clear();
+        self.tx_fifo.clear();
+        self.tx_shift = None;
+        self.baud_ticks = 0;
+        self.rx_idle = 0;
+        self.framing_error = false;
+        self.lcr = UART_LCR_WLEN;
+        self.dll = 0x0c;
+        self.dlm = 0;
+        self.ier = 0;
+        self.iir = UART_IIR_NO_INT;
+        self.mcr = UART_MCR_OUT2;
+        self.lsr = UART_LSR_TEMT | UART_LSR_THRE;
+        self.scr = 0;
+        self.fcr = 0;
+        self.modem_inputs = self.modem_status();
+        self.msr_delta = 0;
     }
 
     fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
         if size != Size::_1 {
-            return Err(Exception::BusException);
+            return Err(Exception::LoadAccessFault { address, size });
         }
-        match address {
+        match address - self.base {
             UART_RBR_DLL => {
                 let res = if self.lcr & UART_LCR_DLAB != 0 {
                     self.dll
                 } else if self.lsr & UART_LSR_BI != 0 {
                     0
-                } else if self.receiver.avaliable() {
-                    self.lsr &= !UART_LSR_OE;
-                    self.receiver.recv()
+                } else if let Some(data) = self.rx_fifo.pop_front() {
+                    self.rx_idle = 0;
+                    if self.rx_fifo.is_empty() {
+                        self.lsr &= !UART_LSR_DR;
+                    }
+                    data
                 } else {
                     0
                 };
@@ -149,33 +430,41 @@ impl Device for Uart {
             } else {
                 self.ier as u64
             }),
-            UART_IIR => Ok(self.iir as u64),
+            UART_IIR => Ok(if self.fcr & UART_FCR_ENABLE_FIFO != 0 {
+                (self.iir | UART_IIR_FIFO_ENABLED) as u64
+            } else {
+                self.iir as u64
+            }),
             UART_LCR => Ok(self.lcr as u64),
             UART_MCR => Ok(self.mcr as u64),
-            UART_LSR => Ok(self.lsr as u64),
+            UART_LSR => {
+                let lsr = self.lsr;
+                // error bits clear once reported
+                self.lsr &= !(UART_LSR_OE | UART_LSR_FE | UART_LSR_BI);
+                Ok(lsr as u64)
+            }
             UART_SCR => Ok(self.scr as u64),
-            UART_MSR => Ok(0),
-            _ => Err(Exception::BusException),
+            UART_MSR => {
+                self.update_modem_status();
+                let msr = self.modem_inputs | self.msr_delta;
+                self.msr_delta = 0;
+                Ok(msr as u64)
+            }
+            _ => Err(Exception::LoadAccessFault { address, size }),
         }
     }
 
     fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
         if size != Size::_1 {
-            return Err(Exception::BusException);
+            return Err(Exception::StoreAccessFault { address, size });
         }
-        match address {
+        match address - self.base {
             UART_THR => {
                 if self.lcr & UART_LCR_DLAB != 0 {
                     self.dll = data as u8;
-                } else if self.fcr & UART_FCR_ENABLE_FIFO == 0 && self.receiver.avaliable() {
-                    self.lsr |= UART_LSR_OE;
-                } else {
-                    self.lsr |= UART_LSR_TEMT | UART_LSR_THRE;
-                    if self.mcr & UART_MCR_LOOP != 0 {
-                        self.loop_sender.send(data as u8);
-                    } else {
-                        self.sender.send(data as u8);
-                    }
+                } else if self.tx_fifo.len() < self.fifo_capacity() {
+                    self.tx_fifo.push_back(data as u8);
+                    self.update_tx_status();
                 }
                 Ok(())
             }
@@ -188,7 +477,19 @@ impl Device for Uart {
                 Ok(())
             }
             UART_FCR => {
-                self.fcr = data as u8;
+                let data = data as u8;
+                // toggling the FIFO enable resets both FIFOs
+                let toggled = (self.fcr ^ data) & UART_FCR_ENABLE_FIFO != 0;
+                if toggled || data & UART_FCR_CLEAR_RCVR != 0 {
+                    self.rx_fifo.clear();
+                    self.lsr &= !UART_LSR_DR;
+                }
+                if toggled || data & UART_FCR_CLEAR_XMIT != 0 {
+                    self.tx_fifo.clear();
+                    self.update_tx_status();
+                }
+                // the clear bits are self-clearing
+                self.fcr = data & !(UART_FCR_CLEAR_RCVR | UART_FCR_CLEAR_XMIT);
                 Ok(())
             }
             UART_LCR => {
@@ -196,14 +497,303 @@ impl Device for Uart {
                 Ok(())
             }
             UART_MCR => {
-                self.mcr = data as u8 & 0b11111;
+                self.mcr = data as u8 & 0b111111;
+                self.update_modem_status();
                 Ok(())
             }
             UART_SCR => {
                 self.scr = data as u8;
                 Ok(())
             }
-            _ => Err(Exception::BusException),
+            _ => Err(Exception::StoreAccessFault { address, size }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one clock per character time, so tests can count characters in clocks
+    fn uart() -> (Uart, Sender<u8>, Receiver<u8>) {
+        let (mut uart, sender, receiver) = Uart::new(&UartConfig::new(UART_START, INTERRUPT_ID));
+        write(&mut uart, UART_LCR, UART_LCR_DLAB | UART_LCR_WLEN);
+        write(&mut uart, UART_RBR_DLL, 1);
+        write(&mut uart, UART_LCR, UART_LCR_WLEN);
+        (uart, sender, receiver)
+    }
+
+    fn read(uart: &mut Uart, register: u32) -> u8 {
+        uart.read(UART_START + register, Size::_1).unwrap() as u8
+    }
+
+    fn write(uart: &mut Uart, register: u32, data: u8) {
+        uart.write(UART_START + register, Size::_1, data as u64)
+            .unwrap();
+    }
+
+    // runs `clocks` clocks and returns the last level of the interrupt line
+    fn clk(uart: &mut Uart, clocks: usize) -> bool {
+        let mut level = false;
+        for _ in 0..clocks {
+            let mut irq = Irq::new();
+            uart.clk(&mut irq);
+            if let Some((_, enable)) = irq.next() {
+                level = enable;
+            }
+        }
+        level
+    }
+
+    #[test]
+    fn full_rx_fifo_overruns() {
+        let (mut uart, sender, _receiver) = uart();
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+        sender.send_all(&[0x55; 17]);
+        clk(&mut uart, 16);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+        clk(&mut uart, 1);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+    }
+
+    fn drain(receiver: &Receiver<u8>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while receiver.avaliable() {
+            bytes.extend(receiver.recv());
+        }
+        bytes
+    }
+
+    #[test]
+    fn thre_tracks_the_tx_fifo() {
+        let (mut uart, _sender, receiver) = uart();
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+        for byte in b"abc" {
+            write(&mut uart, UART_THR, *byte);
+        }
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_THRE, 0);
+
+        // one clock to load the shift register, then one per character
+        clk(&mut uart, 3);
+        let lsr = read(&mut uart, UART_LSR);
+        assert_ne!(lsr & UART_LSR_THRE, 0);
+        assert_eq!(lsr & UART_LSR_TEMT, 0);
+        clk(&mut uart, 1);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_TEMT, 0);
+        assert_eq!(drain(&receiver), b"abc");
+    }
+
+    #[test]
+    fn rdi_waits_for_the_trigger_level() {
+        let (mut uart, sender, _receiver) = uart();
+        // trigger at 8 bytes
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO | 0b10 << 6);
+        write(&mut uart, UART_IER_ILM, UART_IER_RDI);
+        sender.send_all(&[0; 7]);
+        assert!(!clk(&mut uart, 7));
+        assert_eq!(read(&mut uart, UART_IIR) & 0x0f, UART_IIR_NO_INT);
+
+        sender.send(0);
+        assert!(clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR) & 0x0f, UART_IIR_RDI);
+    }
+
+    #[test]
+    fn overrun_sets_on_receive_and_clears_on_lsr_read() {
+        let (mut uart, sender, _receiver) = uart();
+        // FIFO off: a single holding register
+        sender.send_all(b"xy");
+        clk(&mut uart, 2);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+        // the byte already held survives, the new one is lost
+        assert_eq!(read(&mut uart, UART_RBR_DLL), b'x');
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_DR, 0);
+    }
+
+    #[test]
+    fn idle_data_below_trigger_times_out() {
+        let (mut uart, sender, _receiver) = uart();
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO | 0b10 << 6);
+        write(&mut uart, UART_IER_ILM, UART_IER_RDI);
+        sender.send(b'!');
+        assert!(!clk(&mut uart, UART_RX_TIMEOUT as usize));
+        assert!(clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR) & 0x0f, UART_IIR_TIMEOUT);
+
+        assert_eq!(read(&mut uart, UART_RBR_DLL), b'!');
+        assert!(!clk(&mut uart, 1));
+    }
+
+    #[test]
+    fn divisor_paces_the_drain() {
+        let mut drained = Vec::new();
+        for divisor in [1, 4] {
+            let (mut uart, _sender, receiver) = uart();
+            write(&mut uart, UART_LCR, UART_LCR_DLAB | UART_LCR_WLEN);
+            write(&mut uart, UART_RBR_DLL, divisor);
+            write(&mut uart, UART_LCR, UART_LCR_WLEN);
+            write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+            for byte in b"abcd" {
+                write(&mut uart, UART_THR, *byte);
+            }
+            clk(&mut uart, 8);
+            drained.push(drain(&receiver).len());
         }
+        assert_eq!(drained, [4, 1]);
+    }
+
+    #[test]
+    fn loopback_mirrors_mcr_into_msr() {
+        let (mut uart, _sender, _receiver) = uart();
+        write(&mut uart, UART_MCR, UART_MCR_LOOP);
+        assert_eq!(read(&mut uart, UART_MSR) & UART_MSR_CTS, 0);
+        write(&mut uart, UART_MCR, UART_MCR_LOOP | UART_MCR_RTS);
+        assert_ne!(read(&mut uart, UART_MSR) & UART_MSR_CTS, 0);
+        write(
+            &mut uart,
+            UART_MCR,
+            UART_MCR_LOOP | UART_MCR_DTR | UART_MCR_OUT2,
+        );
+        let msr = read(&mut uart, UART_MSR);
+        assert_eq!(msr & 0xf0, UART_MSR_DSR | UART_MSR_DCD);
+    }
+
+    #[test]
+    fn loopback_break_sets_bi() {
+        let (mut uart, sender, _receiver) = uart();
+        write(&mut uart, UART_MCR, UART_MCR_LOOP);
+        write(&mut uart, UART_LCR, UART_LCR_WLEN | UART_LCR_SBC);
+        sender.send(b'A');
+        clk(&mut uart, 1);
+        assert_eq!(read(&mut uart, UART_RBR_DLL), 0);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_BI, 0);
+    }
+
+    #[test]
+    fn seven_data_bits_clear_bit_seven() {
+        let (mut uart, sender, receiver) = uart();
+        write(&mut uart, UART_LCR, 0b10);
+        write(&mut uart, UART_THR, 0xff);
+        clk(&mut uart, 2);
+        assert_eq!(drain(&receiver), [0x7f]);
+
+        sender.send(0xc1);
+        clk(&mut uart, 1);
+        assert_eq!(read(&mut uart, UART_RBR_DLL), 0x41);
+    }
+
+    #[test]
+    fn injected_framing_error_reports_once() {
+        let (mut uart, sender, _receiver) = uart();
+        write(&mut uart, UART_IER_ILM, UART_IER_RLSI);
+        uart.inject_framing_error();
+        sender.send(b'a');
+        assert!(clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR) & 0x0f, UART_IIR_RLSI);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_FE, 0);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_FE, 0);
+
+        // only the next byte carries it
+        sender.send(b'b');
+        clk(&mut uart, 1);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_FE, 0);
+    }
+
+    #[test]
+    fn iir_and_line_agree() {
+        let (mut uart, sender, _receiver) = uart();
+        assert!(!clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR), UART_IIR_NO_INT);
+
+        write(&mut uart, UART_IER_ILM, UART_IER_RDI);
+        sender.send(b'a');
+        assert!(clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR), UART_IIR_RDI);
+
+        read(&mut uart, UART_RBR_DLL);
+        assert!(!clk(&mut uart, 1));
+        assert_eq!(read(&mut uart, UART_IIR), UART_IIR_NO_INT);
+    }
+
+    #[test]
+    fn modem_deltas_latch_until_msr_read() {
+        let (mut uart, _sender, _receiver) = uart();
+        write(&mut uart, UART_MCR, UART_MCR_LOOP);
+        read(&mut uart, UART_MSR);
+        write(&mut uart, UART_MCR, UART_MCR_LOOP | UART_MCR_DTR);
+        clk(&mut uart, 1);
+        assert_ne!(read(&mut uart, UART_MSR) & UART_MSR_DDSR, 0);
+        assert_eq!(read(&mut uart, UART_MSR) & UART_MSR_DDSR, 0);
+
+        // RI's trailing edge only
+        write(&mut uart, UART_MCR, UART_MCR_LOOP | UART_MCR_OUT1);
+        assert_eq!(read(&mut uart, UART_MSR) & UART_MSR_TERI, 0);
+        write(&mut uart, UART_MCR, UART_MCR_LOOP);
+        assert_ne!(read(&mut uart, UART_MSR) & UART_MSR_TERI, 0);
+    }
+
+    #[test]
+    fn cts_gates_tx() {
+        let (mut uart, _sender, receiver) = uart();
+        write(&mut uart, UART_MCR, UART_MCR_AFE | UART_MCR_RTS);
+        uart.set_cts(false);
+        write(&mut uart, UART_THR, b'z');
+        clk(&mut uart, 4);
+        assert!(drain(&receiver).is_empty());
+
+        uart.set_cts(true);
+        clk(&mut uart, 2);
+        assert_eq!(drain(&receiver), b"z");
+    }
+
+    #[test]
+    fn deasserted_rts_holds_off_rx() {
+        let (mut uart, sender, _receiver) = uart();
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+        // trigger at 1 byte, so RTS drops as soon as one arrives
+        write(&mut uart, UART_MCR, UART_MCR_AFE | UART_MCR_RTS);
+        sender.send_all(b"ab");
+        clk(&mut uart, 3);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+        assert_eq!(read(&mut uart, UART_RBR_DLL), b'a');
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_DR, 0);
+
+        clk(&mut uart, 1);
+        assert_eq!(read(&mut uart, UART_RBR_DLL), b'b');
+    }
+
+    #[test]
+    fn deep_fifo_holds_64_bytes() {
+        let (mut uart, sender, _receiver) = Uart::new(&UartConfig {
+            fifo_depth: 64,
+            ..UartConfig::new(UART_START, INTERRUPT_ID)
+        });
+        write(&mut uart, UART_LCR, UART_LCR_DLAB | UART_LCR_WLEN);
+        write(&mut uart, UART_RBR_DLL, 1);
+        write(&mut uart, UART_LCR, UART_LCR_WLEN);
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+        sender.send_all(&[0; 65]);
+        clk(&mut uart, 64);
+        assert_eq!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+        clk(&mut uart, 1);
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_OE, 0);
+    }
+
+    #[test]
+    fn flush_sends_buffered_bytes() {
+        let (mut uart, _sender, receiver) = uart();
+        write(&mut uart, UART_FCR, UART_FCR_ENABLE_FIFO);
+        for byte in b"$ " {
+            write(&mut uart, UART_THR, *byte);
+        }
+        uart.flush();
+        assert_eq!(drain(&receiver), b"$ ");
+        assert_ne!(read(&mut uart, UART_LSR) & UART_LSR_TEMT, 0);
+
+        write(&mut uart, UART_THR, b'#');
+        drop(uart);
+        assert_eq!(receiver.recv(), Some(b'#'));
+        assert_eq!(receiver.recv(), None);
     }
 }
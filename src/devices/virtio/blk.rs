@@ -0,0 +1,328 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::PathBuf,
+};
+
+use crate::{
+    devices::{Device, DmaPort, Irq},
+    utils::{Exception, Size},
+};
+
+use super::{Chain, Mmio};
+
+const VIRTIO_ID_BLOCK: u32 = 2;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+
+const SECTOR_SIZE: u64 = 512;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_T_FLUSH: u32 = 4;
+const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+const VIRTIO_BLK_S_UNSUPP: u8 = 2;
+
+// type, reserved, sector
+const REQUEST_HEADER_SIZE: usize = 16;
+const ID_SIZE: usize = 20;
+
+#[derive(Debug, Clone)]
+pub struct VirtioBlkConfig {
+    pub base: u32,
+    pub interrupt_id: u32,
+    /// Backing image, used in place; its size is rounded down to whole
+    /// 512-byte sectors.
+    pub image: PathBuf,
+}
+
+impl VirtioBlkConfig {
+    pub fn new(base: u32, interrupt_id: u32, image: impl Into<PathBuf>) -> VirtioBlkConfig {
+        VirtioBlkConfig {
+            base,
+            interrupt_id,
+            image: image.into(),
+        }
+    }
+}
+
+/// A virtio-mmio block device with one request queue, backed by an image file.
+#[derive(Debug)]
+pub struct VirtioBlk {
+    mmio: Mmio,
+    disk: Disk,
+}
+
+#[derive(Debug)]
+struct Disk {
+    image: File,
+    sectors: u64,
+}
+
+impl VirtioBlk {
+    pub fn new(config: &VirtioBlkConfig) -> io::Result<VirtioBlk> {
+        let image = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&config.image)?;
+        let sectors = image.metadata()?.len() / SECTOR_SIZE;
+        Ok(VirtioBlk {
+            mmio: Mmio::new(
+                config.base,
+                config.interrupt_id,
+                VIRTIO_ID_BLOCK,
+                VIRTIO_BLK_F_FLUSH,
+                1,
+            ),
+            disk: Disk { image, sectors },
+        })
+    }
+}
+
+impl Disk {
+    // bytes of the image a transfer of `len` bytes at `sector` covers, None
+    // past the end
+    fn extent(&self, sector: u64, len: usize) -> Option<u64> {
+        let end = sector.checked_mul(SECTOR_SIZE)?.checked_add(len as u64)?;
+        (end <= self.sectors * SECTOR_SIZE).then_some(sector * SECTOR_SIZE)
+    }
+
+    // runs one request, returning the status and any data for the driver
+    fn request(&mut self, header: &[u8], data: &[u8], read_len: usize) -> (u8, Vec<u8>) {
+        let kind = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        match kind {
+            VIRTIO_BLK_T_IN => {
+                let Some(offset) = self.extent(sector, read_len) else {
+                    return (VIRTIO_BLK_S_IOERR, Vec::new());
+                };
+                let mut buffer = vec![0; read_len];
+                let result = self
+                    .image
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| self.image.read_exact(&mut buffer));
+                match result {
+                    Ok(()) => (VIRTIO_BLK_S_OK, buffer),
+                    Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            }
+            VIRTIO_BLK_T_OUT => {
+                let Some(offset) = self.extent(sector, data.len()) else {
+                    return (VIRTIO_BLK_S_IOERR, Vec::new());
+                };
+                let result = self
+                    .image
+                    .seek(SeekFrom::Start(offset))
+                    .and_then(|_| self.image.write_all(data));
+                match result {
+                    Ok(()) => (VIRTIO_BLK_S_OK, Vec::new()),
+                    Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+                }
+            }
+            VIRTIO_BLK_T_FLUSH => match self.image.sync_data() {
+                Ok(()) => (VIRTIO_BLK_S_OK, Vec::new()),
+                Err(_) => (VIRTIO_BLK_S_IOERR, Vec::new()),
+            },
+            VIRTIO_BLK_T_GET_ID => {
+                let mut id = b"soc-rs".to_vec();
+                id.resize(ID_SIZE.min(read_len), 0);
+                (VIRTIO_BLK_S_OK, id)
+            }
+            _ => (VIRTIO_BLK_S_UNSUPP, Vec::new()),
+        }
+    }
+
+    fn process(&mut self, chain: &Chain, port: &mut dyn DmaPort) -> Result<u32, Exception> {
+        let input = chain.read(port)?;
+        // the last writable byte is the status
+        let Some(read_len) = chain.writable_len().checked_sub(1) else {
+            return Ok(0);
+        };
+        let (status, mut output) = if input.len() < REQUEST_HEADER_SIZE {
+            (VIRTIO_BLK_S_IOERR, Vec::new())
+        } else {
+            let (header, data) = input.split_at(REQUEST_HEADER_SIZE);
+            self.request(header, data, read_len)
+        };
+        // the status goes after the full data area, whatever was filled
+        output.resize(read_len, 0);
+        output.push(status);
+        chain.write(port, &output)
+    }
+}
+
+impl Device for VirtioBlk {
+    fn name(&self) -> &str {
+        "virtio-blk"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.mmio.address_range()
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        self.mmio.irq(irq);
+    }
+
+    fn reset(&mut self) {
+        self.mmio.reset();
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        // the config space is just the capacity in sectors
+        let config = self.disk.sectors.to_le_bytes();
+        self.mmio.read(address, size, &config)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        self.mmio.write(address, size, data)
+    }
+
+    // requests are handled synchronously when the driver notifies the queue
+    fn dma(&mut self, port: &mut dyn DmaPort) {
+        if self.mmio.take_notified() == 0 {
+            return;
+        }
+        loop {
+            let Some(queue) = self.mmio.queue(0) else {
+                return;
+            };
+            let result = queue.pop(port).and_then(|chain| match chain {
+                Some(chain) => {
+                    let len = self.disk.process(&chain, port)?;
+                    queue.push(port, &chain, len).map(|()| true)
+                }
+                None => Ok(false),
+            });
+            match result {
+                Ok(true) => self.mmio.used_buffer(),
+                Ok(false) => return,
+                Err(_) => return self.mmio.fail(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::memory::{MEMORY_START, Memory};
+
+    const BASE: u32 = 0x1000_8000;
+    const DESC: u32 = MEMORY_START;
+    const AVAIL: u32 = MEMORY_START + 0x1000;
+    const USED: u32 = MEMORY_START + 0x2000;
+    const HEADER: u32 = MEMORY_START + 0x3000;
+    const DATA: u32 = MEMORY_START + 0x4000;
+    const STATUS: u32 = MEMORY_START + 0x5000;
+
+    fn device(image: &[u8]) -> VirtioBlk {
+        let path = std::env::temp_dir().join(format!(
+            "virtio-blk-{}-{}.img",
+            std::process::id(),
+            image.len()
+        ));
+        std::fs::write(&path, image).unwrap();
+        let mut blk = VirtioBlk::new(&VirtioBlkConfig::new(BASE, 1, &path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for (offset, value) in [
+            (0x030, 0),
+            (0x038, 8),
+            (0x080, DESC),
+            (0x090, AVAIL),
+            (0x0a0, USED),
+            (0x044, 1),
+            (0x070, 0b1111),
+        ] {
+            blk.write(BASE + offset, Size::_4, value as u64).unwrap();
+        }
+        blk
+    }
+
+    fn descriptor(memory: &mut Memory, index: u32, buffer: (u32, u32), flags: u16, next: u16) {
+        let mut desc = [0; 16];
+        desc[0..8].copy_from_slice(&(buffer.0 as u64).to_le_bytes());
+        desc[8..12].copy_from_slice(&buffer.1.to_le_bytes());
+        desc[12..14].copy_from_slice(&flags.to_le_bytes());
+        desc[14..16].copy_from_slice(&next.to_le_bytes());
+        memory.dma_write(DESC + 16 * index, &desc).unwrap();
+    }
+
+    // posts a read of `sector` into a `len`-byte buffer and notifies
+    fn submit(blk: &mut VirtioBlk, memory: &mut Memory, sector: u64, data: (u32, u32)) {
+        let mut header = [0; REQUEST_HEADER_SIZE];
+        header[0..4].copy_from_slice(&VIRTIO_BLK_T_IN.to_le_bytes());
+        header[8..16].copy_from_slice(&sector.to_le_bytes());
+        memory.dma_write(HEADER, &header).unwrap();
+        descriptor(memory, 0, (HEADER, REQUEST_HEADER_SIZE as u32), 0b01, 1);
+        descriptor(memory, 1, data, 0b11, 2);
+        descriptor(memory, 2, (STATUS, 1), 0b10, 0);
+        memory.dma_write(AVAIL + 4, &0u16.to_le_bytes()).unwrap();
+        memory.dma_write(AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+        blk.write(BASE + 0x050, Size::_4, 0).unwrap();
+        blk.dma(memory);
+    }
+
+    #[test]
+    fn read_request_copies_the_sector() {
+        let image: Vec<u8> = (0..4 * SECTOR_SIZE as usize)
+            .map(|i| (i / 3) as u8)
+            .collect();
+        let mut blk = device(&image);
+        let mut memory = Memory::new();
+        submit(&mut blk, &mut memory, 2, (DATA, SECTOR_SIZE as u32));
+
+        let mut data = vec![0; SECTOR_SIZE as usize];
+        memory.dma_read(DATA, &mut data).unwrap();
+        assert_eq!(
+            data,
+            image[2 * SECTOR_SIZE as usize..3 * SECTOR_SIZE as usize]
+        );
+        let mut status = [0xff];
+        memory.dma_read(STATUS, &mut status).unwrap();
+        assert_eq!(status, [VIRTIO_BLK_S_OK]);
+        let mut used = [0; 12];
+        memory.dma_read(USED, &mut used).unwrap();
+        assert_eq!(u16::from_le_bytes([used[2], used[3]]), 1);
+        assert_eq!(u32::from_le_bytes(used[8..12].try_into().unwrap()), 513);
+        assert_eq!(blk.read(BASE + 0x060, Size::_4), Ok(1));
+    }
+
+    #[test]
+    fn buffers_outside_ram_fail_the_device() {
+        let mut blk = device(&[0; SECTOR_SIZE as usize]);
+        let mut memory = Memory::new();
+        submit(&mut blk, &mut memory, 0, (MEMORY_START - 0x100, u32::MAX));
+        let mut used = [0; 4];
+        memory.dma_read(USED, &mut used).unwrap();
+        assert_eq!(u16::from_le_bytes([used[2], used[3]]), 0);
+        // NEEDS_RESET
+        assert_ne!(blk.read(BASE + 0x070, Size::_4).unwrap() & 0x40, 0);
+    }
+
+    #[test]
+    fn wrapped_ring_address_fails_the_device() {
+        let mut blk = device(&[0; SECTOR_SIZE as usize]);
+        let mut memory = Memory::new();
+        blk.write(BASE + 0x090, Size::_4, 0xffff_ffff).unwrap();
+        blk.write(BASE + 0x050, Size::_4, 0).unwrap();
+        blk.dma(&mut memory);
+        // NEEDS_RESET
+        assert_ne!(blk.read(BASE + 0x070, Size::_4).unwrap() & 0x40, 0);
+    }
+
+    #[test]
+    fn descriptor_index_past_the_table_fails_the_device() {
+        let mut blk = device(&[0; SECTOR_SIZE as usize]);
+        let mut memory = Memory::new();
+        // head 8 in a table of 8
+        memory.dma_write(AVAIL + 4, &8u16.to_le_bytes()).unwrap();
+        memory.dma_write(AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+        blk.write(BASE + 0x050, Size::_4, 0).unwrap();
+        blk.dma(&mut memory);
+        assert_ne!(blk.read(BASE + 0x070, Size::_4).unwrap() & 0x40, 0);
+    }
+}
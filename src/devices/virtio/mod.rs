@@ -0,0 +1,400 @@
+// virtio over MMIO (version 2, "modern"), with split virtqueues; the device
+// types build on `Mmio` for the transport and add their config space and
+// request handling
+use std::ops::Range;
+
+use crate::utils::{Exception, Size};
+
+use super::{
+    DmaPort, Irq,
+    memory::{MEMORY_SIZE, in_ram},
+};
+
+pub mod blk;
+pub mod net;
+
+pub(crate) const VIRTIO_SIZE: u32 = 0x1000;
+
+const VIRTIO_MAGIC: u32 = 0x74726976;
+const VIRTIO_VERSION: u32 = 2;
+const VIRTIO_VENDOR: u32 = 0x554d4551;
+
+const VIRTIO_MMIO_MAGIC_VALUE: u32 = 0x000;
+const VIRTIO_MMIO_VERSION: u32 = 0x004;
+const VIRTIO_MMIO_DEVICE_ID: u32 = 0x008;
+const VIRTIO_MMIO_VENDOR_ID: u32 = 0x00c;
+const VIRTIO_MMIO_DEVICE_FEATURES: u32 = 0x010;
+const VIRTIO_MMIO_DEVICE_FEATURES_SEL: u32 = 0x014;
+const VIRTIO_MMIO_DRIVER_FEATURES: u32 = 0x020;
+const VIRTIO_MMIO_DRIVER_FEATURES_SEL: u32 = 0x024;
+const VIRTIO_MMIO_QUEUE_SEL: u32 = 0x030;
+const VIRTIO_MMIO_QUEUE_NUM_MAX: u32 = 0x034;
+const VIRTIO_MMIO_QUEUE_NUM: u32 = 0x038;
+const VIRTIO_MMIO_QUEUE_READY: u32 = 0x044;
+const VIRTIO_MMIO_QUEUE_NOTIFY: u32 = 0x050;
+const VIRTIO_MMIO_INTERRUPT_STATUS: u32 = 0x060;
+const VIRTIO_MMIO_INTERRUPT_ACK: u32 = 0x064;
+const VIRTIO_MMIO_STATUS: u32 = 0x070;
+const VIRTIO_MMIO_QUEUE_DESC_LOW: u32 = 0x080;
+const VIRTIO_MMIO_QUEUE_DESC_HIGH: u32 = 0x084;
+const VIRTIO_MMIO_QUEUE_DRIVER_LOW: u32 = 0x090;
+const VIRTIO_MMIO_QUEUE_DRIVER_HIGH: u32 = 0x094;
+const VIRTIO_MMIO_QUEUE_DEVICE_LOW: u32 = 0x0a0;
+const VIRTIO_MMIO_QUEUE_DEVICE_HIGH: u32 = 0x0a4;
+const VIRTIO_MMIO_CONFIG_GENERATION: u32 = 0x0fc;
+const VIRTIO_MMIO_CONFIG: u32 = 0x100;
+
+const VIRTIO_STATUS_DRIVER_OK: u32 = 0b00000100;
+const VIRTIO_STATUS_NEEDS_RESET: u32 = 0b01000000;
+
+const VIRTIO_INT_USED_BUFFER: u32 = 0b01;
+const VIRTIO_INT_CONFIG_CHANGE: u32 = 0b10;
+
+pub(crate) const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const VIRTQ_DESC_F_NEXT: u16 = 0b01;
+const VIRTQ_DESC_F_WRITE: u16 = 0b10;
+
+const QUEUE_NUM_MAX: u32 = 256;
+
+// guest-physical addresses are 64-bit in the queue layout, but the bus is not
+fn address(address: u64) -> Result<u32, Exception> {
+    u32::try_from(address).map_err(|_| Exception::LoadAccessFault {
+        address: address as u32,
+        size: Size::_8,
+    })
+}
+
+fn read_u16(port: &mut dyn DmaPort, address: u32) -> Result<u16, Exception> {
+    let mut data = [0; 2];
+    port.dma_read(address, &mut data)?;
+    Ok(u16::from_le_bytes(data))
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Queue {
+    num: u32,
+    ready: bool,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    // next available ring entry to consume
+    last_avail: u16,
+}
+
+// a descriptor chain popped from the available ring: the device reads the
+// `readable` buffers and fills the `writable` ones, in order. `pop` checks
+// every buffer lies in RAM and the buffers total no more than RAM, so
+// their sizes are safe to allocate
+#[derive(Debug)]
+pub(crate) struct Chain {
+    head: u16,
+    readable: Vec<(u32, u32)>,
+    writable: Vec<(u32, u32)>,
+}
+
+impl Chain {
+    // the concatenated readable buffers
+    pub(crate) fn read(&self, port: &mut dyn DmaPort) -> Result<Vec<u8>, Exception> {
+        let mut data = vec![0; self.readable.iter().map(|&(_, len)| len as usize).sum()];
+        let mut offset = 0;
+        for &(address, len) in &self.readable {
+            port.dma_read(address, &mut data[offset..offset + len as usize])?;
+            offset += len as usize;
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn writable_len(&self) -> usize {
+        self.writable.iter().map(|&(_, len)| len as usize).sum()
+    }
+
+    // scatters `data` over the writable buffers, truncating it if they're
+    // too small; returns the number of bytes written
+    pub(crate) fn write(&self, port: &mut dyn DmaPort, data: &[u8]) -> Result<u32, Exception> {
+        let mut offset = 0;
+        for &(address, len) in &self.writable {
+            let len = (len as usize).min(data.len() - offset);
+            port.dma_write(address, &data[offset..offset + len])?;
+            offset += len;
+        }
+        Ok(offset as u32)
+    }
+}
+
+impl Queue {
+    pub(crate) fn pop(&mut self, port: &mut dyn DmaPort) -> Result<Option<Chain>, Exception> {
+        let driver = address(self.driver)?;
+        let desc = address(self.desc)?;
+        // the guest picks the ring addresses, so they can wrap
+        let ring = |base: u32, offset: u32, size| {
+            base.checked_add(offset).ok_or(Exception::LoadAccessFault {
+                address: base,
+                size,
+            })
+        };
+        let idx = read_u16(port, ring(driver, 2, Size::_2)?)?;
+        if idx == self.last_avail {
+            return Ok(None);
+        }
+        let slot = self.last_avail as u32 % self.num;
+        let head = read_u16(port, ring(driver, 4 + 2 * slot, Size::_2)?)?;
+        self.last_avail = self.last_avail.wrapping_add(1);
+
+        let mut chain = Chain {
+            head,
+            readable: Vec::new(),
+            writable: Vec::new(),
+        };
+        let mut index = head;
+        let mut total = 0;
+        // a chain can't be longer than the ring, which also stops loops
+        for _ in 0..self.num {
+            // outside the descriptor table
+            if index as u32 >= self.num {
+                return Err(Exception::LoadAccessFault {
+                    address: desc,
+                    size: Size::_16,
+                });
+            }
+            let mut entry = [0; 16];
+            port.dma_read(ring(desc, 16 * index as u32, Size::_16)?, &mut entry)?;
+            let buffer = (
+                address(u64::from_le_bytes(entry[0..8].try_into().unwrap()))?,
+                u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+            );
+            total += buffer.1 as u64;
+            if !in_ram(buffer.0, buffer.1 as u64) || total > MEMORY_SIZE as u64 {
+                return Err(Exception::LoadAccessFault {
+                    address: buffer.0,
+                    size: Size::_1,
+                });
+            }
+            let flags = u16::from_le_bytes([entry[12], entry[13]]);
+            if flags & VIRTQ_DESC_F_WRITE != 0 {
+                chain.writable.push(buffer);
+            } else {
+                chain.readable.push(buffer);
+            }
+            if flags & VIRTQ_DESC_F_NEXT == 0 {
+                return Ok(Some(chain));
+            }
+            index = u16::from_le_bytes([entry[14], entry[15]]);
+        }
+        Err(Exception::LoadAccessFault {
+            address: desc,
+            size: Size::_16,
+        })
+    }
+
+    // returns a chain to the driver, `len` being the bytes written into it
+    pub(crate) fn push(
+        &mut self,
+        port: &mut dyn DmaPort,
+        chain: &Chain,
+        len: u32,
+    ) -> Result<(), Exception> {
+        let device = address(self.device)?;
+        let ring = |offset: u32, size| {
+            device
+                .checked_add(offset)
+                .ok_or(Exception::StoreAccessFault {
+                    address: device,
+                    size,
+                })
+        };
+        let idx = read_u16(port, ring(2, Size::_2)?)?;
+        let slot = idx as u32 % self.num;
+        let mut elem = [0; 8];
+        elem[0..4].copy_from_slice(&(chain.head as u32).to_le_bytes());
+        elem[4..8].copy_from_slice(&len.to_le_bytes());
+        port.dma_write(ring(4 + 8 * slot, Size::_8)?, &elem)?;
+        port.dma_write(ring(2, Size::_2)?, &idx.wrapping_add(1).to_le_bytes())
+    }
+}
+
+// the transport registers common to every virtio device
+#[derive(Debug)]
+pub(crate) struct Mmio {
+    base: u32,
+    interrupt_id: u32,
+    device_id: u32,
+    device_features: u64,
+    device_features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Queue>,
+    // queues notified since the last `take_notified`, one bit each
+    notified: u32,
+    interrupt_status: u32,
+    status: u32,
+}
+
+impl Mmio {
+    pub(crate) fn new(
+        base: u32,
+        interrupt_id: u32,
+        device_id: u32,
+        device_features: u64,
+        queues: usize,
+    ) -> Mmio {
+        Mmio {
+            base,
+            interrupt_id,
+            device_id,
+            device_features: device_features | VIRTIO_F_VERSION_1,
+            device_features_sel: 0,
+            driver_features: 0,
+            driver_features_sel: 0,
+            queue_sel: 0,
+            queues: vec![Queue::default(); queues],
+            notified: 0,
+            interrupt_status: 0,
+            status: 0,
+        }
+    }
+
+    pub(crate) fn address_range(&self) -> Range<u32> {
+        self.base..self.base + VIRTIO_SIZE
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Mmio::new(
+            self.base,
+            self.interrupt_id,
+            self.device_id,
+            self.device_features,
+            self.queues.len(),
+        );
+    }
+
+    pub(crate) fn irq(&self, irq: &mut Irq) {
+        irq.irq(self.interrupt_id, self.interrupt_status != 0);
+    }
+
+    // queues the driver has notified since the last call, as a bitmask
+    pub(crate) fn take_notified(&mut self) -> u32 {
+        std::mem::take(&mut self.notified)
+    }
+
+    // the queue, once the driver has set up both it and the device
+    pub(crate) fn queue(&mut self, index: usize) -> Option<&mut Queue> {
+        if self.status & VIRTIO_STATUS_DRIVER_OK == 0
+            || self.status & VIRTIO_STATUS_NEEDS_RESET != 0
+        {
+            return None;
+        }
+        self.queues.get_mut(index).filter(|queue| queue.ready)
+    }
+
+    pub(crate) fn used_buffer(&mut self) {
+        self.interrupt_status |= VIRTIO_INT_USED_BUFFER;
+    }
+
+    // the driver broke the queue protocol; it has to reset the device
+    pub(crate) fn fail(&mut self) {
+        self.status |= VIRTIO_STATUS_NEEDS_RESET;
+        self.interrupt_status |= VIRTIO_INT_CONFIG_CHANGE;
+    }
+
+    pub(crate) fn read(
+        &mut self,
+        address: u32,
+        size: Size,
+        config: &[u8],
+    ) -> Result<u64, Exception> {
+        let offset = address - self.base;
+        if offset >= VIRTIO_MMIO_CONFIG {
+            let start = (offset - VIRTIO_MMIO_CONFIG) as usize;
+            let mut data = [0; 8];
+            if size.bytes() > 8 || !size.is_aligned(address) || start + size.bytes() > config.len()
+            {
+                return Err(Exception::LoadAccessFault { address, size });
+            }
+            data[..size.bytes()].copy_from_slice(&config[start..start + size.bytes()]);
+            return Ok(u64::from_le_bytes(data));
+        }
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let queue = self.queues.get(self.queue_sel as usize);
+        let data = match offset {
+            VIRTIO_MMIO_MAGIC_VALUE => VIRTIO_MAGIC,
+            VIRTIO_MMIO_VERSION => VIRTIO_VERSION,
+            VIRTIO_MMIO_DEVICE_ID => self.device_id,
+            VIRTIO_MMIO_VENDOR_ID => VIRTIO_VENDOR,
+            VIRTIO_MMIO_DEVICE_FEATURES => match self.device_features_sel {
+                0 => self.device_features as u32,
+                1 => (self.device_features >> 32) as u32,
+                _ => 0,
+            },
+            VIRTIO_MMIO_QUEUE_NUM_MAX => queue.map_or(0, |_| QUEUE_NUM_MAX),
+            VIRTIO_MMIO_QUEUE_READY => queue.is_some_and(|queue| queue.ready) as u32,
+            VIRTIO_MMIO_INTERRUPT_STATUS => self.interrupt_status,
+            VIRTIO_MMIO_STATUS => self.status,
+            // the config space never changes under the driver
+            VIRTIO_MMIO_CONFIG_GENERATION => 0,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    pub(crate) fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        let offset = address - self.base;
+        if size != Size::_4 || offset >= VIRTIO_MMIO_CONFIG {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        let set_low = |value: &mut u64| *value = (*value & !0xFFFF_FFFF) | data as u64;
+        let set_high = |value: &mut u64| *value = (*value & 0xFFFF_FFFF) | (data as u64) << 32;
+        match offset {
+            VIRTIO_MMIO_DEVICE_FEATURES_SEL => self.device_features_sel = data,
+            VIRTIO_MMIO_DRIVER_FEATURES => match self.driver_features_sel {
+                0 => set_low(&mut self.driver_features),
+                1 => set_high(&mut self.driver_features),
+                _ => {}
+            },
+            VIRTIO_MMIO_DRIVER_FEATURES_SEL => self.driver_features_sel = data,
+            VIRTIO_MMIO_QUEUE_SEL => self.queue_sel = data,
+            VIRTIO_MMIO_QUEUE_NOTIFY => {
+                if (data as usize) < self.queues.len() {
+                    self.notified |= 1 << data;
+                }
+            }
+            VIRTIO_MMIO_INTERRUPT_ACK => self.interrupt_status &= !data,
+            VIRTIO_MMIO_STATUS => {
+                if data == 0 {
+                    self.reset();
+                } else {
+                    self.status = data;
+                }
+            }
+            _ => {
+                // the rest configure the selected queue
+                let Some(queue) = self.queues.get_mut(self.queue_sel as usize) else {
+                    return Ok(());
+                };
+                match offset {
+                    VIRTIO_MMIO_QUEUE_NUM => {
+                        if data.is_power_of_two() && data <= QUEUE_NUM_MAX {
+                            queue.num = data;
+                        }
+                    }
+                    VIRTIO_MMIO_QUEUE_READY => {
+                        queue.ready = data & 1 != 0 && queue.num != 0;
+                        queue.last_avail = 0;
+                    }
+                    VIRTIO_MMIO_QUEUE_DESC_LOW => set_low(&mut queue.desc),
+                    VIRTIO_MMIO_QUEUE_DESC_HIGH => set_high(&mut queue.desc),
+                    VIRTIO_MMIO_QUEUE_DRIVER_LOW => set_low(&mut queue.driver),
+                    VIRTIO_MMIO_QUEUE_DRIVER_HIGH => set_high(&mut queue.driver),
+                    VIRTIO_MMIO_QUEUE_DEVICE_LOW => set_low(&mut queue.device),
+                    VIRTIO_MMIO_QUEUE_DEVICE_HIGH => set_high(&mut queue.device),
+                    _ => return Err(Exception::StoreAccessFault { address, size }),
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::ops::Range;
+
+use crate::{
+    devices::{Device, DmaPort, Irq},
+    utils::{
+        Exception, Size,
+        channel::{Receiver, Sender, channel},
+    },
+};
+
+use super::Mmio;
+
+const VIRTIO_ID_NET: u32 = 1;
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+
+const RECEIVEQ: usize = 0;
+const TRANSMITQ: usize = 1;
+
+// struct virtio_net_hdr with num_buffers, as laid out once VERSION_1 is
+// negotiated; no offloads are offered, so only num_buffers is ever set
+const NET_HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone)]
+pub struct VirtioNetConfig {
+    pub base: u32,
+    pub interrupt_id: u32,
+    pub mac: [u8; 6],
+}
+
+impl VirtioNetConfig {
+    /// A locally administered MAC address is picked; set `mac` to override.
+    pub fn new(base: u32, interrupt_id: u32) -> VirtioNetConfig {
+        VirtioNetConfig {
+            base,
+            interrupt_id,
+            mac: [0x52, 0x54, 0x00, 0x12, 0x34, 0x56],
+        }
+    }
+}
+
+/// A virtio-mmio network device. Ethernet frames the guest transmits come out
+/// of the returned receiver; frames sent into the returned sender are delivered
+/// to the guest, queued until it posts receive buffers.
+#[derive(Debug)]
+pub struct VirtioNet {
+    mmio: Mmio,
+    mac: [u8; 6],
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+    // a received frame waiting for the guest to post a buffer
+    pending: Option<Vec<u8>>,
+}
+
+impl VirtioNet {
+    pub fn new(config: &VirtioNetConfig) -> (VirtioNet, Sender<Vec<u8>>, Receiver<Vec<u8>>) {
+        let (recv_send, recv) = channel();
+        let (send, send_recv) = channel();
+        (
+            VirtioNet {
+                mmio: Mmio::new(
+                    config.base,
+                    config.interrupt_id,
+                    VIRTIO_ID_NET,
+                    VIRTIO_NET_F_MAC,
+                    2,
+                ),
+                mac: config.mac,
+                sender: send,
+                receiver: recv,
+                pending: None,
+            },
+            recv_send,
+            send_recv,
+        )
+    }
+
+    // hands transmitted frames to the host; returns whether any were used
+    fn transmit(&mut self, port: &mut dyn DmaPort) -> Result<bool, Exception> {
+        let mut used = false;
+        while let Some(queue) = self.mmio.queue(TRANSMITQ)
+            && let Some(chain) = queue.pop(port)?
+        {
+            let packet = chain.read(port)?;
+            if packet.len() > NET_HEADER_SIZE {
+                self.sender.send(packet[NET_HEADER_SIZE..].to_vec());
+            }
+            queue.push(port, &chain, 0)?;
+            used = true;
+        }
+        Ok(used)
+    }
+
+    // delivers host frames into posted receive buffers, one frame per chain
+    fn receive(&mut self, port: &mut dyn DmaPort) -> Result<bool, Exception> {
+        let mut used = false;
+        loop {
+            if self.pending.is_none() && self.receiver.avaliable() {
+                self.pending = self.receiver.recv();
+            }
+            let Some(frame) = &self.pending else {
+                return Ok(used);
+            };
+            let Some(queue) = self.mmio.queue(RECEIVEQ) else {
+                return Ok(used);
+            };
+            let Some(chain) = queue.pop(port)? else {
+                return Ok(used);
+            };
+            let mut packet = vec![0; NET_HEADER_SIZE];
+            // num_buffers
+            packet[10] = 1;
+            packet.extend_from_slice(frame);
+            let len = chain.write(port, &packet)?;
+            queue.push(port, &chain, len)?;
+            self.pending = None;
+            used = true;
+        }
+    }
+}
+
+impl Device for VirtioNet {
+    fn name(&self) -> &str {
+        "virtio-net"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.mmio.address_range()
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        self.mmio.irq(irq);
+    }
+
+    // frames already queued by the host survive the reset
+    fn reset(&mut self) {
+        self.mmio.reset();
+        self.pending = None;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        // the config space is just the MAC address
+        let config = self.mac;
+        self.mmio.read(address, size, &config)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        self.mmio.write(address, size, data)
+    }
+
+    // transmits when the driver notifies; receiving also runs on every clock
+    // as frames arrive from the host
+    fn dma(&mut self, port: &mut dyn DmaPort) {
+        let notified = self.mmio.take_notified();
+        let transmitted = if notified & (1 << TRANSMITQ) != 0 {
+            self.transmit(port)
+        } else {
+            Ok(false)
+        };
+        let result = transmitted.and_then(|tx| Ok(self.receive(port)? || tx));
+        match result {
+            Ok(true) => self.mmio.used_buffer(),
+            Ok(false) => {}
+            Err(_) => self.mmio.fail(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::memory::{MEMORY_START, Memory};
+
+    const BASE: u32 = 0x1000_9000;
+    const DESC: u32 = MEMORY_START;
+    const AVAIL: u32 = MEMORY_START + 0x1000;
+    const USED: u32 = MEMORY_START + 0x2000;
+    const PACKET: u32 = MEMORY_START + 0x3000;
+
+    #[test]
+    fn transmitted_frame_reaches_the_host() {
+        let (mut net, _frames, transmitted) = VirtioNet::new(&VirtioNetConfig::new(BASE, 2));
+        for (offset, value) in [
+            (0x030, TRANSMITQ as u32),
+            (0x038, 8),
+            (0x080, DESC),
+            (0x090, AVAIL),
+            (0x0a0, USED),
+            (0x044, 1),
+            (0x070, 0b1111),
+        ] {
+            net.write(BASE + offset, Size::_4, value as u64).unwrap();
+        }
+
+        let mut memory = Memory::new();
+        let frame: Vec<u8> = (0..60).collect();
+        let mut packet = vec![0; NET_HEADER_SIZE];
+        packet.extend_from_slice(&frame);
+        memory.dma_write(PACKET, &packet).unwrap();
+        let mut desc = [0; 16];
+        desc[0..8].copy_from_slice(&(PACKET as u64).to_le_bytes());
+        desc[8..12].copy_from_slice(&(packet.len() as u32).to_le_bytes());
+        memory.dma_write(DESC, &desc).unwrap();
+        memory.dma_write(AVAIL + 2, &1u16.to_le_bytes()).unwrap();
+
+        net.write(BASE + 0x050, Size::_4, TRANSMITQ as u64).unwrap();
+        net.dma(&mut memory);
+        assert_eq!(transmitted.recv(), Some(frame));
+        let mut used = [0; 2];
+        memory.dma_read(USED + 2, &mut used).unwrap();
+        assert_eq!(u16::from_le_bytes(used), 1);
+        assert_eq!(net.read(BASE + 0x060, Size::_4), Ok(1));
+    }
+}
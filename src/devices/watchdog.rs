@@ -0,0 +1,154 @@
+use std::ops::Range;
+
+use crate::utils::{
+    Exception, Size,
+    channel::{Receiver, Sender, channel},
+};
+
+use super::{Device, Irq};
+
+pub(crate) const WATCHDOG_SIZE: u32 = 0x100;
+
+// enabling starts the countdown from TIMEOUT
+const WATCHDOG_CTRL: u32 = 0x00;
+// in device clocks
+const WATCHDOG_TIMEOUT: u32 = 0x04;
+// any write restarts the countdown
+const WATCHDOG_PET: u32 = 0x08;
+// clocks left before expiry, read-only
+const WATCHDOG_REMAINING: u32 = 0x0c;
+
+const WATCHDOG_CTRL_ENABLE: u32 = 0b1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogCommand {
+    /// The guest didn't pet the watchdog in time; the system should be
+    /// reset, e.g. with `Bus::reset`.
+    Reset,
+}
+
+/// A watchdog timer counting down in device clocks. If the guest doesn't pet it
+/// before the countdown runs out, it disables itself and sends
+/// `WatchdogCommand::Reset` through the returned receiver.
+#[derive(Debug)]
+pub struct Watchdog {
+    base: u32,
+    sender: Sender<WatchdogCommand>,
+    ctrl: u32,
+    timeout: u32,
+    remaining: u32,
+}
+
+impl Watchdog {
+    pub fn new(base: u32) -> (Watchdog, Receiver<WatchdogCommand>) {
+        let (sender, receiver) = channel();
+        (
+            Watchdog {
+                base,
+                sender,
+                ctrl: 0,
+                timeout: 0,
+                remaining: 0,
+            },
+            receiver,
+        )
+    }
+}
+
+impl Device for Watchdog {
+    fn name(&self) -> &str {
+        "watchdog"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        self.base..self.base + WATCHDOG_SIZE
+    }
+
+    fn clk(&mut self, _irq: &mut Irq) {
+        if self.ctrl & WATCHDOG_CTRL_ENABLE == 0 {
+            return;
+        }
+        self.remaining = self.remaining.saturating_sub(1);
+        if self.remaining == 0 {
+            self.ctrl &= !WATCHDOG_CTRL_ENABLE;
+            self.sender.send(WatchdogCommand::Reset);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ctrl = 0;
+        self.timeout = 0;
+        self.remaining = 0;
+    }
+
+    fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if size != Size::_4 {
+            return Err(Exception::LoadAccessFault { address, size });
+        }
+        let data = match address - self.base {
+            WATCHDOG_CTRL => self.ctrl,
+            WATCHDOG_TIMEOUT => self.timeout,
+            WATCHDOG_REMAINING => self.remaining,
+            _ => return Err(Exception::LoadAccessFault { address, size }),
+        };
+        Ok(data as u64)
+    }
+
+    fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if size != Size::_4 {
+            return Err(Exception::StoreAccessFault { address, size });
+        }
+        let data = data as u32;
+        match address - self.base {
+            WATCHDOG_CTRL => {
+                if data & WATCHDOG_CTRL_ENABLE != 0 && self.ctrl & WATCHDOG_CTRL_ENABLE == 0 {
+                    self.remaining = self.timeout;
+                }
+                self.ctrl = data;
+            }
+            // takes effect at the next pet
+            WATCHDOG_TIMEOUT => self.timeout = data,
+            WATCHDOG_PET => self.remaining = self.timeout,
+            _ => return Err(Exception::StoreAccessFault { address, size }),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x1000_7000;
+
+    fn clk(watchdog: &mut Watchdog, clocks: u32) {
+        let mut irq = Irq::new();
+        for _ in 0..clocks {
+            watchdog.clk(&mut irq);
+        }
+    }
+
+    #[test]
+    fn petting_holds_off_the_reset() {
+        let (mut watchdog, commands) = Watchdog::new(BASE);
+        watchdog
+            .write(BASE + WATCHDOG_TIMEOUT, Size::_4, 10)
+            .unwrap();
+        watchdog
+            .write(BASE + WATCHDOG_CTRL, Size::_4, WATCHDOG_CTRL_ENABLE as u64)
+            .unwrap();
+        for _ in 0..5 {
+            clk(&mut watchdog, 9);
+            watchdog.write(BASE + WATCHDOG_PET, Size::_4, 0).unwrap();
+        }
+        assert!(!commands.avaliable());
+
+        clk(&mut watchdog, 9);
+        assert!(!commands.avaliable());
+        clk(&mut watchdog, 1);
+        assert_eq!(commands.recv(), Some(WatchdogCommand::Reset));
+        assert_eq!(watchdog.read(BASE + WATCHDOG_CTRL, Size::_4), Ok(0));
+        clk(&mut watchdog, 20);
+        assert!(!commands.avaliable());
+    }
+}
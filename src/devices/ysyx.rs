@@ -1,277 +1,918 @@
 use std::{
     collections::LinkedList,
     fmt::Debug,
-    time::{SystemTime, UNIX_EPOCH},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 
-use sdl2::{
-    EventPump, event::Event, keyboard::Scancode, pixels::PixelMasks, surface::Surface,
-    video::Window,
-};
+use sdl2::rect::Rect;
 
 use crate::utils::{
     Exception,
     channel::{Receiver, Sender, channel},
-    u32_to_u8,
 };
 
-use super::{Device, Irq, Size};
+use super::{
+    Device, DmaPort, Irq, Size,
+    framebuffer::{Framebuffer, FramebufferConfig},
+    memory::in_ram,
+    sdl::FrontendCommand,
+};
+
+pub use super::framebuffer::PixelFormat;
 
 pub(crate) const YSYX_START: u32 = 0x20000000;
 pub(crate) const YSYX_END: u32 = YSYX_START + 0x10000000 - 1;
 
-const VGA_WIDTH: usize = 800 / 2;
-const VGA_HEIGHT: usize = 600 / 2;
 const KEYDOWN: u32 = 0x8000;
 
+// milliseconds since start (or the last `reset_timer`); a 32-bit read of LOW
+// latches HIGH so the two halves always belong to the same sample, a 64-bit
+// read of LOW returns the whole counter
 const YSYX_TIME_LOW: u32 = YSYX_START;
-const YSYX_TIME_HIGH: u32 = YSYX_START + 8;
+const YSYX_TIME_HIGH: u32 = YSYX_START + 4;
 
-const YSYX_VGACTL_ADDR_LOW: u32 = YSYX_START + 0x100;
-// sync
-const YSYX_VGACTL_ADDR_HIGH: u32 = YSYX_START + 0x100 + 4;
+// host wall clock in UTC, as abstract-machine's AM_TIMER_RTC: second,
+// minute, hour, day, month, year, one word each
+const YSYX_RTC_START: u32 = YSYX_START + 0x10;
+const YSYX_RTC_END: u32 = YSYX_START + 0x24;
+
+const YSYX_VGACTL_ADDR: u32 = YSYX_START + 0x100;
 
 const YSYX_KBD_ADDR: u32 = YSYX_START + 0x200;
 
+// one packet per read, 0 when empty: dx in bits 0..8 and dy in bits 8..16 as
+// i8, buttons (left, right, middle) in bits 16..19, bit 31 marks a packet
+const YSYX_MOUSE_ADDR: u32 = YSYX_START + 0x210;
+const MOUSE_VALID: u32 = 0x80000000;
+
+// gamepad state rather than a queue: one bit per pressed button, and axes 0
+// and 1 as i16 in the low and high half
+const YSYX_GAMEPAD_BUTTONS: u32 = YSYX_START + 0x220;
+const YSYX_GAMEPAD_AXES: u32 = YSYX_START + 0x224;
+
 const YSYX_POWEROFF: u32 = YSYX_START + 0x300;
 
+// abstract-machine audio: the guest sets freq/channels/samples, writes 1 to
+// INIT, then streams signed 16-bit samples into SBUF; COUNT reads the bytes
+// still queued for playback
+const YSYX_AUDIO_FREQ: u32 = YSYX_START + 0x400;
+const YSYX_AUDIO_CHANNELS: u32 = YSYX_START + 0x404;
+const YSYX_AUDIO_SAMPLES: u32 = YSYX_START + 0x408;
+const YSYX_AUDIO_SBUF_SIZE: u32 = YSYX_START + 0x40c;
+const YSYX_AUDIO_INIT: u32 = YSYX_START + 0x410;
+const YSYX_AUDIO_COUNT: u32 = YSYX_START + 0x414;
+
+// abstract-machine disk: the guest fills in BUF/BLKNO/COUNT and writes a
+// command; the transfer to or from guest RAM has completed once the write
+// returns, with STATUS reporting whether it succeeded
+const YSYX_DISK_PRESENT: u32 = YSYX_START + 0x500;
+const YSYX_DISK_BLKSZ: u32 = YSYX_START + 0x504;
+const YSYX_DISK_BLKCNT: u32 = YSYX_START + 0x508;
+// followed by BLKNO at +0x510
+const YSYX_DISK_BUF: u32 = YSYX_START + 0x50c;
+const YSYX_DISK_COUNT: u32 = YSYX_START + 0x514;
+const YSYX_DISK_CMD: u32 = YSYX_START + 0x518;
+const YSYX_DISK_STATUS: u32 = YSYX_START + 0x51c;
+const DISK_BLOCK_SIZE: u64 = 512;
+const DISK_CMD_READ: u32 = 1;
+const DISK_CMD_WRITE: u32 = 2;
+
+const YSYX_AUDIO_SBUF_START: u32 = YSYX_START + 0x02000000;
+const YSYX_AUDIO_SBUF_SIZE_BYTES: u32 = 0x10000;
+const YSYX_AUDIO_SBUF_END: u32 = YSYX_AUDIO_SBUF_START + YSYX_AUDIO_SBUF_SIZE_BYTES - 1;
+
 const YSYX_FB_START: u32 = YSYX_START + 0x01000000;
-const YSYX_FB_END: u32 = YSYX_FB_START + ((VGA_WIDTH * VGA_HEIGHT * 4) as u32) - 1;
+
+#[derive(Debug, Clone)]
+pub struct YsyxConfig {
+    /// Host image backing the disk device; without one the guest sees no disk.
+    pub disk: Option<PathBuf>,
+    /// Framebuffer size in pixels, reported to the guest through VGACTL;
+    /// neither can be 0.
+    pub width: u16,
+    pub height: u16,
+    pub format: PixelFormat,
+    /// Run without a window, audio or SDL at all; the framebuffer is only
+    /// visible to the guest and through MMIO. Otherwise SDL runs on its own
+    /// frontend thread.
+    pub headless: bool,
+    /// Present at most this many frames per second; syncs arriving sooner
+    /// are folded into the next frame. `None` presents every sync.
+    pub max_fps: Option<u32>,
+    /// Key events, and separately mouse packets, buffered for the guest; once
+    /// full the oldest is dropped.
+    pub key_queue_depth: usize,
+}
+
+impl Default for YsyxConfig {
+    fn default() -> Self {
+        YsyxConfig {
+            disk: None,
+            width: 800 / 2,
+            height: 600 / 2,
+            format: PixelFormat::default(),
+            headless: false,
+            max_fps: None,
+            key_queue_depth: 16,
+        }
+    }
+}
+
+/// Input for the guest, from the SDL window or injected by the embedder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YsyxEvent {
+    /// `code` is an abstract-machine keycode.
+    Key {
+        code: u32,
+        down: bool,
+    },
+    MouseMotion {
+        dx: i32,
+        dy: i32,
+    },
+    /// `button` is 0 for left, 1 for right and 2 for middle.
+    MouseButton {
+        button: u8,
+        down: bool,
+    },
+    GamepadButton {
+        button: u8,
+        down: bool,
+    },
+    GamepadAxis {
+        axis: u8,
+        value: i16,
+    },
+    /// The window was closed.
+    Quit,
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum YsyxCommand {
-    Poweroff,
+    /// The guest powered off with this exit code; closing the window reports
+    /// 0.
+    Poweroff(u8),
 }
 
-pub(crate) struct Ysyx {
+pub struct Ysyx {
     sender: Sender<YsyxCommand>,
+    events: Receiver<YsyxEvent>,
 
-    window: Window,
-    event_pump: EventPump,
-    vgactl: [u32; 2],
-    vmem: [u32; VGA_WIDTH * VGA_HEIGHT],
+    // VGACTL and the framebuffer, which also owns the window
+    fb: Framebuffer,
+    timer_base: Instant,
+    time_high: u32,
     key_queue: LinkedList<u32>,
+    key_queue_depth: usize,
+    mouse_queue: LinkedList<u32>,
+    mouse_buttons: u32,
+    gamepad_buttons: u32,
+    gamepad_axes: [i16; 2],
+
+    // freq, channels, samples
+    audio_ctl: [u32; 3],
+    // low byte of a sample split across two writes
+    audio_partial: Option<u8>,
+
+    disk: Option<File>,
+    disk_blocks: u32,
+    // buf, blkno, count
+    disk_ctl: [u32; 3],
+    disk_command: Option<u32>,
+    disk_status: u32,
 }
 
 impl Debug for Ysyx {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Ysyx")
-            .field("vgactl", &self.vgactl)
-            .field("vmem", &self.vmem)
-            .finish()
+        f.debug_struct("Ysyx").field("fb", &self.fb).finish()
     }
 }
 
 impl Ysyx {
-    pub(crate) fn new() -> (Ysyx, Receiver<YsyxCommand>) {
+    // fails if the screen size is invalid, or the disk image or the window
+    // can't be opened
+    pub(crate) fn new(
+        config: &YsyxConfig,
+    ) -> io::Result<(Ysyx, Receiver<YsyxCommand>, Sender<YsyxEvent>)> {
         let (send, recv) = channel();
-        let sdl_ctx = sdl2::init().unwrap();
-        let video = sdl_ctx.video().unwrap();
-        let window = video
-            .window("ysyx", 1024, 768)
-            .position_centered()
-            .opengl()
-            .build()
-            .unwrap();
-        let event_pump = sdl_ctx.event_pump().unwrap();
-        (
+        let (event_sender, events) = channel();
+        let fb = FramebufferConfig {
+            format: config.format,
+            headless: config.headless,
+            max_fps: config.max_fps,
+            ..FramebufferConfig::new(YSYX_VGACTL_ADDR, YSYX_FB_START, config.width, config.height)
+        };
+        let disk = config
+            .disk
+            .as_ref()
+            .map(|path| OpenOptions::new().read(true).write(true).open(path))
+            .transpose()?;
+        let disk_blocks = match &disk {
+            Some(disk) => (disk.metadata()?.len() / DISK_BLOCK_SIZE) as u32,
+            None => 0,
+        };
+        Ok((
             Ysyx {
                 sender: send,
-                window,
-                event_pump,
-                vgactl: [((VGA_WIDTH << 16) | VGA_HEIGHT) as u32, 0],
-                vmem: [0; VGA_WIDTH * VGA_HEIGHT],
+                events,
+                fb: Framebuffer::with_events(&fb, event_sender.clone())?,
+                timer_base: Instant::now(),
+                time_high: 0,
                 key_queue: LinkedList::new(),
+                key_queue_depth: config.key_queue_depth.max(1),
+                mouse_queue: LinkedList::new(),
+                mouse_buttons: 0,
+                gamepad_buttons: 0,
+                gamepad_axes: [0; 2],
+                audio_ctl: [0; 3],
+                audio_partial: None,
+                disk,
+                disk_blocks,
+                disk_ctl: [0; 3],
+                disk_command: None,
+                disk_status: 0,
             },
             recv,
-        )
+            event_sender,
+        ))
     }
-}
 
-impl Device for Ysyx {
-    fn clk(&mut self, _irq: &mut Irq) {
-        for event in self.event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => self.sender.send(YsyxCommand::Poweroff),
-                Event::KeyDown {
-                    scancode: Some(scancode),
-                    ..
+    fn handle_event(&mut self, event: YsyxEvent) {
+        match event {
+            YsyxEvent::Quit => self.sender.send(YsyxCommand::Poweroff(0)),
+            YsyxEvent::Key { code, down } => self.push_key(code | if down { KEYDOWN } else { 0 }),
+            YsyxEvent::MouseMotion { dx, dy } => self.push_mouse(dx, dy),
+            YsyxEvent::MouseButton { button, down } if button < 3 => {
+                if down {
+                    self.mouse_buttons |= 1 << button;
+                } else {
+                    self.mouse_buttons &= !(1 << button);
                 }
-                | Event::KeyUp {
-                    scancode: Some(scancode),
-                    ..
-                } => {
-                    if let Some(amcode) = keycode_to_amkey(scancode) {
-                        let down = if let Event::KeyDown { .. } = event {
-                            KEYDOWN
-                        } else {
-                            0
-                        };
-                        self.key_queue.push_back(amcode | down);
-                    }
+                self.push_mouse(0, 0);
+            }
+            YsyxEvent::GamepadButton { button, down } if button < 32 => {
+                if down {
+                    self.gamepad_buttons |= 1 << button;
+                } else {
+                    self.gamepad_buttons &= !(1 << button);
                 }
-                _ => {}
             }
+            YsyxEvent::GamepadAxis { axis, value } if axis < 2 => {
+                self.gamepad_axes[axis as usize] = value;
+            }
+            _ => {}
+        }
+    }
+
+    fn push_key(&mut self, key: u32) {
+        if self.key_queue.len() >= self.key_queue_depth {
+            self.key_queue.pop_front();
+        }
+        self.key_queue.push_back(key);
+    }
+
+    fn push_mouse(&mut self, dx: i32, dy: i32) {
+        let dx = dx.clamp(i8::MIN as i32, i8::MAX as i32) as u8 as u32;
+        let dy = dy.clamp(i8::MIN as i32, i8::MAX as i32) as u8 as u32;
+        if self.mouse_queue.len() >= self.key_queue_depth {
+            self.mouse_queue.pop_front();
+        }
+        self.mouse_queue
+            .push_back(MOUSE_VALID | (self.mouse_buttons << 16) | (dy << 8) | dx);
+    }
+
+    fn audio_init(&mut self) {
+        let [freq, channels, samples] = self.audio_ctl;
+        self.audio_partial = None;
+        if let Some(frontend) = self.fb.frontend() {
+            frontend.send(FrontendCommand::AudioInit {
+                freq,
+                channels,
+                samples,
+            });
+        }
+    }
+
+    /// The framebuffer as the guest sees it: row-major pixels in the
+    /// configured format, one per word for `Xrgb8888` and two for `Rgb565`.
+    pub fn framebuffer(&self) -> &[u32] {
+        self.fb.pixels()
+    }
+
+    /// Saves the framebuffer as an RGB PNG.
+    pub fn screenshot(&self, path: &Path) -> io::Result<()> {
+        self.fb.screenshot(path)
+    }
+
+    /// Restarts the guest-visible uptime counter from zero.
+    pub fn reset_timer(&mut self) {
+        self.timer_base = Instant::now();
+        self.time_high = 0;
+    }
+
+    fn uptime(&self) -> u64 {
+        self.timer_base.elapsed().as_millis() as u64
+    }
+
+    /// Frames presented so far; coalesced syncs count once.
+    pub fn frame_count(&self) -> u64 {
+        self.fb.frame_count()
+    }
+
+    /// The region written since the last presented frame, in framebuffer
+    /// pixels.
+    pub fn dirty_rect(&self) -> Option<Rect> {
+        self.fb.dirty_rect()
+    }
+
+    /// A copy of the front buffer: the framebuffer as of the last presented
+    /// frame, unaffected by drawing since.
+    pub fn displayed_frame(&self) -> Vec<u32> {
+        self.fb.displayed_frame()
+    }
+
+    fn disk_transfer(&mut self, command: u32, port: &mut dyn DmaPort) -> Option<()> {
+        let [buffer, block, count] = self.disk_ctl;
+        if block.checked_add(count)? > self.disk_blocks {
+            return None;
+        }
+        let len = count as u64 * DISK_BLOCK_SIZE;
+        // the buffer is only allocated once the guest's side is known to fit
+        if !in_ram(buffer, len) {
+            return None;
+        }
+        let disk = self.disk.as_mut()?;
+        let mut data = vec![0; len as usize];
+        disk.seek(SeekFrom::Start(block as u64 * DISK_BLOCK_SIZE))
+            .ok()?;
+        match command {
+            DISK_CMD_READ => {
+                disk.read_exact(&mut data).ok()?;
+                port.dma_write(buffer, &data).ok()
+            }
+            DISK_CMD_WRITE => {
+                port.dma_read(buffer, &mut data).ok()?;
+                disk.write_all(&data).ok()
+            }
+            _ => None,
+        }
+    }
+
+    fn audio_write(&mut self, bytes: &[u8]) {
+        let samples = self.audio_samples(bytes);
+        if let Some(frontend) = self.fb.frontend() {
+            frontend.send(FrontendCommand::Audio(samples));
+        }
+    }
+
+    // little-endian samples, a trailing odd byte carried over to the next write
+    fn audio_samples(&mut self, bytes: &[u8]) -> Vec<i16> {
+        let mut samples = Vec::with_capacity(bytes.len() / 2 + 1);
+        for &byte in bytes {
+            match self.audio_partial.take() {
+                Some(low) => samples.push(i16::from_le_bytes([low, byte])),
+                None => self.audio_partial = Some(byte),
+            }
+        }
+        samples
+    }
+}
+
+impl Device for Ysyx {
+    fn name(&self) -> &str {
+        "ysyx"
+    }
+
+    fn address_range(&self) -> Range<u32> {
+        YSYX_START..YSYX_END + 1
+    }
+
+    // the disk image and the window stay open; the screen is blanked
+    fn reset(&mut self) {
+        self.reset_timer();
+        self.fb.reset();
+        self.key_queue.clear();
+        self.mouse_queue.clear();
+        self.mouse_buttons = 0;
+        self.gamepad_buttons = 0;
+        self.gamepad_axes = [0; 2];
+        self.audio_ctl = [0; 3];
+        self.audio_partial = None;
+        self.disk_ctl = [0; 3];
+        self.disk_command = None;
+        self.disk_status = 0;
+    }
+
+    fn clk(&mut self, irq: &mut Irq) {
+        self.fb.clk(irq);
+
+        while self.events.avaliable() {
+            if let Some(event) = self.events.recv() {
+                self.handle_event(event);
+            }
+        }
+    }
+
+    fn dma(&mut self, port: &mut dyn DmaPort) {
+        if let Some(command) = self.disk_command.take() {
+            self.disk_status = match self.disk_transfer(command, port) {
+                Some(()) => 0,
+                None => 1,
+            };
         }
     }
 
     fn read(&mut self, address: u32, size: Size) -> Result<u64, Exception> {
+        if self.fb.contains(address) {
+            return self.fb.read(address, size);
+        }
         match size {
             Size::_4 => match address {
-                YSYX_VGACTL_ADDR_LOW => Ok(self.vgactl[0] as u64),
-                YSYX_VGACTL_ADDR_HIGH => Ok(self.vgactl[1] as u64),
-                YSYX_FB_START..=YSYX_FB_END => {
-                    Ok(self.vmem[((address - YSYX_FB_START) / 4) as usize] as u64)
+                YSYX_TIME_LOW => {
+                    let uptime = self.uptime();
+                    self.time_high = (uptime >> 32) as u32;
+                    Ok(uptime as u32 as u64)
+                }
+                YSYX_TIME_HIGH => Ok(self.time_high as u64),
+                YSYX_RTC_START..=YSYX_RTC_END if address.is_multiple_of(4) => {
+                    Ok(rtc()[((address - YSYX_RTC_START) / 4) as usize] as u64)
                 }
                 YSYX_KBD_ADDR => Ok((if !self.key_queue.is_empty() {
                     self.key_queue.pop_front().unwrap()
                 } else {
                     0
                 }) as u64),
-                _ => Err(Exception::BusException),
+                YSYX_MOUSE_ADDR => Ok(self.mouse_queue.pop_front().unwrap_or(0) as u64),
+                YSYX_GAMEPAD_BUTTONS => Ok(self.gamepad_buttons as u64),
+                YSYX_GAMEPAD_AXES => Ok((((self.gamepad_axes[1] as u16 as u32) << 16)
+                    | self.gamepad_axes[0] as u16 as u32)
+                    as u64),
+                YSYX_DISK_PRESENT => Ok(self.disk.is_some() as u64),
+                YSYX_DISK_BLKSZ => Ok(DISK_BLOCK_SIZE),
+                YSYX_DISK_BLKCNT => Ok(self.disk_blocks as u64),
+                YSYX_DISK_BUF..=YSYX_DISK_COUNT if address.is_multiple_of(4) => {
+                    Ok(self.disk_ctl[((address - YSYX_DISK_BUF) / 4) as usize] as u64)
+                }
+                YSYX_DISK_STATUS => Ok(self.disk_status as u64),
+                YSYX_AUDIO_FREQ => Ok(self.audio_ctl[0] as u64),
+                YSYX_AUDIO_CHANNELS => Ok(self.audio_ctl[1] as u64),
+                YSYX_AUDIO_SAMPLES => Ok(self.audio_ctl[2] as u64),
+                YSYX_AUDIO_SBUF_SIZE => Ok(YSYX_AUDIO_SBUF_SIZE_BYTES as u64),
+                YSYX_AUDIO_COUNT => Ok(self
+                    .fb
+                    .frontend()
+                    .map_or(0, |frontend| frontend.audio_queued())
+                    as u64),
+                _ => Err(Exception::LoadAccessFault { address, size }),
             },
             Size::_8 => match address {
-                YSYX_TIME_LOW => Ok(SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64),
-                YSYX_TIME_HIGH => Ok((SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis()
-                    >> 64) as u64),
-                _ => Err(Exception::BusException),
+                YSYX_TIME_LOW => Ok(self.uptime()),
+                _ => Err(Exception::LoadAccessFault { address, size }),
             },
-            _ => Err(Exception::BusException),
+            _ => Err(Exception::LoadAccessFault { address, size }),
         }
     }
 
     fn write(&mut self, address: u32, size: Size, data: u64) -> Result<(), Exception> {
+        if self.fb.contains(address) {
+            return self.fb.write(address, size, data);
+        }
+        if let YSYX_AUDIO_SBUF_START..=YSYX_AUDIO_SBUF_END = address {
+            if size == Size::_16 {
+                return Err(Exception::StoreAccessFault { address, size });
+            }
+            self.audio_write(&data.to_le_bytes()[..size.bytes()]);
+            return Ok(());
+        }
         match size {
             Size::_1 => match address {
                 YSYX_POWEROFF => {
                     println!("exit with code {:x}", data);
-                    self.sender.send(YsyxCommand::Poweroff);
+                    self.sender.send(YsyxCommand::Poweroff(data as u8));
                     Ok(())
                 }
-                _ => Err(Exception::BusException),
+                _ => Err(Exception::StoreAccessFault { address, size }),
             },
             Size::_4 => match address {
-                YSYX_VGACTL_ADDR_HIGH => {
-                    let surface = Surface::from_data_pixelmasks(
-                        u32_to_u8(&mut self.vmem),
-                        VGA_WIDTH as u32,
-                        VGA_HEIGHT as u32,
-                        VGA_WIDTH as u32 * 4,
-                        &PixelMasks {
-                            bpp: 32,
-                            rmask: 0x00ff0000,
-                            gmask: 0x0000ff00,
-                            bmask: 0x000000ff,
-                            amask: 0x00000000,
-                        },
-                    )
-                    .unwrap();
-                    let mut w_surface = self.window.surface(&self.event_pump).unwrap();
-                    surface.blit_scaled(None, &mut w_surface, None).unwrap();
-                    w_surface.finish().unwrap();
+                YSYX_AUDIO_FREQ..=YSYX_AUDIO_SAMPLES if address.is_multiple_of(4) => {
+                    self.audio_ctl[((address - YSYX_AUDIO_FREQ) / 4) as usize] = data as u32;
                     Ok(())
                 }
-                YSYX_FB_START..=YSYX_FB_END => {
-                    self.vmem[((address - YSYX_FB_START) / 4) as usize] = data as u32;
+                YSYX_DISK_BUF..=YSYX_DISK_COUNT if address.is_multiple_of(4) => {
+                    self.disk_ctl[((address - YSYX_DISK_BUF) / 4) as usize] = data as u32;
                     Ok(())
                 }
-                _ => Err(Exception::BusException),
+                YSYX_DISK_CMD => {
+                    self.disk_command = Some(data as u32);
+                    Ok(())
+                }
+                YSYX_AUDIO_INIT => {
+                    if data & 1 != 0 {
+                        self.audio_init();
+                    }
+                    Ok(())
+                }
+                _ => Err(Exception::StoreAccessFault { address, size }),
             },
-            _ => Err(Exception::BusException),
+            _ => Err(Exception::StoreAccessFault { address, size }),
         }
     }
 }
 
-fn keycode_to_amkey(scancode: Scancode) -> Option<u32> {
-    match scancode {
-        Scancode::Escape => Some(1),
-        Scancode::F1 => Some(2),
-        Scancode::F2 => Some(3),
-        Scancode::F3 => Some(4),
-        Scancode::F4 => Some(5),
-        Scancode::F5 => Some(6),
-        Scancode::F6 => Some(7),
-        Scancode::F7 => Some(8),
-        Scancode::F8 => Some(9),
-        Scancode::F9 => Some(10),
-        Scancode::F10 => Some(11),
-        Scancode::F11 => Some(12),
-        Scancode::F12 => Some(13),
-        Scancode::Grave => Some(14),
-        Scancode::Num1 => Some(15),
-        Scancode::Num2 => Some(16),
-        Scancode::Num3 => Some(17),
-        Scancode::Num4 => Some(18),
-        Scancode::Num5 => Some(19),
-        Scancode::Num6 => Some(20),
-        Scancode::Num7 => Some(21),
-        Scancode::Num8 => Some(22),
-        Scancode::Num9 => Some(23),
-        Scancode::Num0 => Some(24),
-        Scancode::Minus => Some(25),
-        Scancode::Equals => Some(26),
-        Scancode::Backspace => Some(27),
-        Scancode::Tab => Some(28),
-        Scancode::Q => Some(29),
-        Scancode::W => Some(30),
-        Scancode::E => Some(31),
-        Scancode::R => Some(32),
-        Scancode::T => Some(33),
-        Scancode::Y => Some(34),
-        Scancode::U => Some(35),
-        Scancode::I => Some(36),
-        Scancode::O => Some(37),
-        Scancode::P => Some(38),
-        Scancode::LeftBracket => Some(39),
-        Scancode::RightBracket => Some(40),
-        Scancode::Backslash => Some(41),
-        Scancode::CapsLock => Some(42),
-        Scancode::A => Some(43),
-        Scancode::S => Some(44),
-        Scancode::D => Some(45),
-        Scancode::F => Some(46),
-        Scancode::G => Some(47),
-        Scancode::H => Some(48),
-        Scancode::J => Some(49),
-        Scancode::K => Some(50),
-        Scancode::L => Some(51),
-        Scancode::Semicolon => Some(52),
-        Scancode::Apostrophe => Some(53),
-        Scancode::Return => Some(54),
-        Scancode::LShift => Some(55),
-        Scancode::Z => Some(56),
-        Scancode::X => Some(57),
-        Scancode::C => Some(58),
-        Scancode::V => Some(59),
-        Scancode::B => Some(60),
-        Scancode::N => Some(61),
-        Scancode::M => Some(62),
-        Scancode::Comma => Some(63),
-        Scancode::Period => Some(64),
-        Scancode::Slash => Some(65),
-        Scancode::RShift => Some(66),
-        Scancode::LCtrl => Some(67),
-        Scancode::Application => Some(68),
-        Scancode::LAlt => Some(69),
-        Scancode::Space => Some(70),
-        Scancode::RAlt => Some(71),
-        Scancode::RCtrl => Some(72),
-        Scancode::Up => Some(73),
-        Scancode::Down => Some(74),
-        Scancode::Left => Some(75),
-        Scancode::Right => Some(76),
-        Scancode::Insert => Some(77),
-        Scancode::Delete => Some(78),
-        Scancode::Home => Some(79),
-        Scancode::End => Some(80),
-        Scancode::PageUp => Some(81),
-        Scancode::PageDown => Some(82),
-        _ => None,
+fn rtc() -> [u32; 6] {
+    civil(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    )
+}
+
+// seconds since the Unix epoch to the RTC fields
+fn civil(seconds: u64) -> [u32; 6] {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // days since 1970-01-01 to a civil date, after Howard Hinnant's
+    // civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    [
+        (time % 60) as u32,
+        (time / 60 % 60) as u32,
+        (time / 3600) as u32,
+        day as u32,
+        month as u32,
+        year as u32,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn headless() -> (Ysyx, Receiver<YsyxCommand>, Sender<YsyxEvent>) {
+        Ysyx::new(&YsyxConfig {
+            headless: true,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn clk(ysyx: &mut Ysyx) {
+        ysyx.clk(&mut Irq::new());
+    }
+
+    fn read(ysyx: &mut Ysyx, address: u32) -> u32 {
+        ysyx.read(address, Size::_4).unwrap() as u32
+    }
+
+    #[test]
+    fn mouse_motion_reads_as_a_packet() {
+        let (mut ysyx, _commands, events) = headless();
+        events.send(YsyxEvent::MouseButton {
+            button: 0,
+            down: true,
+        });
+        events.send(YsyxEvent::MouseMotion { dx: 5, dy: -3 });
+        clk(&mut ysyx);
+        assert_eq!(read(&mut ysyx, YSYX_MOUSE_ADDR), MOUSE_VALID | 1 << 16);
+        assert_eq!(
+            read(&mut ysyx, YSYX_MOUSE_ADDR),
+            MOUSE_VALID | 1 << 16 | 0xfd << 8 | 5
+        );
+        assert_eq!(read(&mut ysyx, YSYX_MOUSE_ADDR), 0);
+    }
+
+    #[test]
+    fn mouse_queue_drops_the_oldest() {
+        let (mut ysyx, _commands, events) = headless();
+        for dx in 1..=20 {
+            events.send(YsyxEvent::MouseMotion { dx, dy: 0 });
+        }
+        clk(&mut ysyx);
+        assert_eq!(ysyx.mouse_queue.len(), 16);
+        assert_eq!(read(&mut ysyx, YSYX_MOUSE_ADDR) & 0xff, 5);
+    }
+
+    #[test]
+    fn audio_stream_assembles_samples() {
+        let (mut ysyx, _commands, _events) = headless();
+        for (address, value) in [
+            (YSYX_AUDIO_FREQ, 44100),
+            (YSYX_AUDIO_CHANNELS, 2),
+            (YSYX_AUDIO_SAMPLES, 1024),
+        ] {
+            ysyx.write(address, Size::_4, value).unwrap();
+            assert_eq!(read(&mut ysyx, address), value as u32);
+        }
+        ysyx.write(YSYX_AUDIO_INIT, Size::_4, 1).unwrap();
+
+        // 7 bytes make 3 samples, the odd one waits for its high byte
+        assert_eq!(ysyx.audio_samples(&[0x34, 0x12, 0xff]), [0x1234]);
+        assert_eq!(
+            ysyx.audio_samples(&[0xff, 0x00, 0x80, 0x01]),
+            [-1, i16::MIN]
+        );
+        assert_eq!(ysyx.audio_partial, Some(0x01));
+        assert_eq!(read(&mut ysyx, YSYX_AUDIO_SBUF_SIZE), 0x10000);
+    }
+
+    #[test]
+    fn disk_read_lands_in_memory() {
+        use crate::devices::memory::{MEMORY_START, Memory};
+
+        let path = std::env::temp_dir().join(format!("ysyx-disk-{}.img", std::process::id()));
+        let image: Vec<u8> = (0..3 * DISK_BLOCK_SIZE).map(|i| (i / 7) as u8).collect();
+        std::fs::write(&path, &image).unwrap();
+        let (mut ysyx, _commands, _events) = Ysyx::new(&YsyxConfig {
+            disk: Some(path.clone()),
+            headless: true,
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read(&mut ysyx, YSYX_DISK_PRESENT), 1);
+        assert_eq!(read(&mut ysyx, YSYX_DISK_BLKCNT), 3);
+
+        let mut memory = Memory::new();
+        let buffer = MEMORY_START + 0x1000;
+        for (address, value) in [
+            (YSYX_DISK_BUF, buffer),
+            (YSYX_DISK_BUF + 4, 1),
+            (YSYX_DISK_COUNT, 2),
+            (YSYX_DISK_CMD, DISK_CMD_READ),
+        ] {
+            ysyx.write(address, Size::_4, value as u64).unwrap();
+        }
+        ysyx.dma(&mut memory);
+        assert_eq!(read(&mut ysyx, YSYX_DISK_STATUS), 0);
+
+        let mut data = vec![0; 2 * DISK_BLOCK_SIZE as usize];
+        memory.dma_read(buffer, &mut data).unwrap();
+        assert_eq!(data, image[DISK_BLOCK_SIZE as usize..]);
+
+        // past the end of the image
+        ysyx.write(YSYX_DISK_BUF + 4, Size::_4, 2).unwrap();
+        ysyx.write(YSYX_DISK_CMD, Size::_4, DISK_CMD_READ as u64)
+            .unwrap();
+        ysyx.dma(&mut memory);
+        assert_eq!(read(&mut ysyx, YSYX_DISK_STATUS), 1);
+    }
+
+    #[test]
+    fn vgactl_reports_the_configured_size() {
+        let (mut ysyx, _commands, _events) = Ysyx::new(&YsyxConfig {
+            width: 640,
+            height: 480,
+            headless: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(read(&mut ysyx, YSYX_VGACTL_ADDR), 640 << 16 | 480);
+        assert_eq!(ysyx.framebuffer().len(), 640 * 480);
+    }
+
+    #[test]
+    fn headless_framebuffer_reads_back() {
+        let (mut ysyx, _commands, _events) = headless();
+        ysyx.write(YSYX_FB_START + 8, Size::_4, 0x00ff_8000)
+            .unwrap();
+        assert_eq!(read(&mut ysyx, YSYX_FB_START + 8), 0x00ff_8000);
+        ysyx.write(YSYX_VGACTL_ADDR + 4, Size::_4, 1).unwrap();
+        assert_eq!(ysyx.displayed_frame()[2], 0x00ff_8000);
+    }
+
+    #[test]
+    fn screenshot_of_a_solid_fill() {
+        let (mut ysyx, _commands, _events) = Ysyx::new(&YsyxConfig {
+            width: 4,
+            height: 2,
+            headless: true,
+            ..Default::default()
+        })
+        .unwrap();
+        for pixel in 0..8 {
+            ysyx.write(YSYX_FB_START + pixel * 4, Size::_4, 0x0012_3456)
+                .unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("ysyx-shot-{}.png", std::process::id()));
+        ysyx.screenshot(&path).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!((info.width, info.height), (4, 2));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert!(
+            data.chunks_exact(3)
+                .all(|pixel| pixel == [0x12, 0x34, 0x56])
+        );
+    }
+
+    #[test]
+    fn framebuffer_slice_matches_mmio() {
+        let (mut ysyx, _commands, _events) = headless();
+        let (x, y, width) = (7, 3, 400);
+        let index = y * width + x;
+        ysyx.write(YSYX_FB_START + 4 * index as u32, Size::_4, 0xabcdef)
+            .unwrap();
+        assert_eq!(ysyx.framebuffer()[index], 0xabcdef);
+        assert_eq!(ysyx.framebuffer().iter().filter(|&&p| p != 0).count(), 1);
+    }
+
+    #[test]
+    fn frame_cap_coalesces_syncs() {
+        let (mut ysyx, _commands, _events) = Ysyx::new(&YsyxConfig {
+            headless: true,
+            max_fps: Some(60),
+            ..Default::default()
+        })
+        .unwrap();
+        for _ in 0..5 {
+            ysyx.write(YSYX_VGACTL_ADDR + 4, Size::_4, 1).unwrap();
+        }
+        assert_eq!(ysyx.frame_count(), 1);
+        assert_eq!(read(&mut ysyx, YSYX_VGACTL_ADDR + 4), 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+        clk(&mut ysyx);
+        assert_eq!(ysyx.frame_count(), 2);
+        assert_eq!(read(&mut ysyx, YSYX_VGACTL_ADDR + 4), 0);
+    }
+
+    #[test]
+    fn gamepad_button_sets_its_bit() {
+        let (mut ysyx, _commands, events) = headless();
+        events.send(YsyxEvent::GamepadButton {
+            button: 3,
+            down: true,
+        });
+        events.send(YsyxEvent::GamepadAxis { axis: 1, value: -2 });
+        clk(&mut ysyx);
+        assert_eq!(read(&mut ysyx, YSYX_GAMEPAD_BUTTONS), 1 << 3);
+        assert_eq!(read(&mut ysyx, YSYX_GAMEPAD_AXES), 0xfffe_0000);
+
+        events.send(YsyxEvent::GamepadButton {
+            button: 3,
+            down: false,
+        });
+        clk(&mut ysyx);
+        assert_eq!(read(&mut ysyx, YSYX_GAMEPAD_BUTTONS), 0);
+    }
+
+    #[test]
+    fn key_queue_keeps_the_newest() {
+        let (mut ysyx, _commands, events) = Ysyx::new(&YsyxConfig {
+            headless: true,
+            key_queue_depth: 4,
+            ..Default::default()
+        })
+        .unwrap();
+        for code in 1..=6 {
+            events.send(YsyxEvent::Key { code, down: true });
+        }
+        clk(&mut ysyx);
+        assert_eq!(ysyx.key_queue.len(), 4);
+        let keys: Vec<u32> = (0..5).map(|_| read(&mut ysyx, YSYX_KBD_ADDR)).collect();
+        assert_eq!(
+            keys,
+            [3 | KEYDOWN, 4 | KEYDOWN, 5 | KEYDOWN, 6 | KEYDOWN, 0]
+        );
+    }
+
+    #[test]
+    fn timer_halves_read_coherently() {
+        let (mut ysyx, _commands, _events) = headless();
+        let mut last = 0;
+        for _ in 0..3 {
+            let low = read(&mut ysyx, YSYX_TIME_LOW) as u64;
+            let high = read(&mut ysyx, YSYX_TIME_HIGH) as u64;
+            let now = high << 32 | low;
+            assert!(now >= last);
+            last = now;
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert!(last >= 4);
+
+        // the high word is latched by the low read
+        ysyx.time_high = 7;
+        assert_eq!(read(&mut ysyx, YSYX_TIME_HIGH), 7);
+
+        ysyx.reset_timer();
+        assert!(ysyx.read(YSYX_TIME_LOW, Size::_8).unwrap() < last);
+    }
+
+    #[test]
+    fn rtc_fields_are_plausible() {
+        let (mut ysyx, _commands, _events) = headless();
+        let [second, minute, hour, day, month, year] =
+            [0, 1, 2, 3, 4, 5].map(|i| read(&mut ysyx, YSYX_RTC_START + 4 * i));
+        assert!(second < 60 && minute < 60 && hour < 24);
+        assert!((1..=31).contains(&day) && (1..=12).contains(&month));
+        assert!((2024..2200).contains(&year));
+    }
+
+    #[test]
+    fn civil_date_conversion() {
+        // 2000-02-29 12:34:56 UTC
+        assert_eq!(civil(951_827_696), [56, 34, 12, 29, 2, 2000]);
+        assert_eq!(civil(0), [0, 0, 0, 1, 1, 1970]);
+    }
+
+    #[test]
+    fn poweroff_carries_the_exit_code() {
+        let (mut ysyx, commands, events) = headless();
+        ysyx.write(YSYX_POWEROFF, Size::_1, 0x2a).unwrap();
+        assert!(matches!(commands.recv(), Some(YsyxCommand::Poweroff(0x2a))));
+
+        // closing the window is a clean exit
+        events.send(YsyxEvent::Quit);
+        clk(&mut ysyx);
+        assert!(matches!(commands.recv(), Some(YsyxCommand::Poweroff(0))));
+    }
+
+    #[test]
+    fn sync_handshake_presents_once() {
+        let (mut ysyx, _commands, _events) = headless();
+        // anything but 1 is ignored
+        ysyx.write(YSYX_VGACTL_ADDR + 4, Size::_4, 0).unwrap();
+        assert_eq!(ysyx.frame_count(), 0);
+
+        ysyx.write(YSYX_VGACTL_ADDR + 4, Size::_4, 1).unwrap();
+        assert_eq!(read(&mut ysyx, YSYX_VGACTL_ADDR + 4), 0);
+        clk(&mut ysyx);
+        assert_eq!(ysyx.frame_count(), 1);
+    }
+
+    #[test]
+    fn injected_keydown_reaches_the_guest() {
+        let (mut ysyx, _commands, events) = headless();
+        events.send(YsyxEvent::Key {
+            code: 43,
+            down: true,
+        });
+        assert_eq!(read(&mut ysyx, YSYX_KBD_ADDR), 0);
+        clk(&mut ysyx);
+        assert_eq!(read(&mut ysyx, YSYX_KBD_ADDR), 43 | KEYDOWN);
+    }
+
+    #[test]
+    fn disk_buffer_outside_ram_fails() {
+        use crate::devices::memory::{MEMORY_END, Memory};
+
+        let path = std::env::temp_dir().join(format!("ysyx-disk-ram-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0x5a; 2 * DISK_BLOCK_SIZE as usize]).unwrap();
+        let (mut ysyx, _commands, _events) = Ysyx::new(&YsyxConfig {
+            disk: Some(path.clone()),
+            headless: true,
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut memory = Memory::new();
+        for buffer in [0x1000, MEMORY_END - 0x100] {
+            for (address, value) in [
+                (YSYX_DISK_BUF, buffer),
+                (YSYX_DISK_BUF + 4, 0),
+                (YSYX_DISK_COUNT, 2),
+                (YSYX_DISK_CMD, DISK_CMD_READ),
+            ] {
+                ysyx.write(address, Size::_4, value as u64).unwrap();
+            }
+            ysyx.dma(&mut memory);
+            assert_eq!(read(&mut ysyx, YSYX_DISK_STATUS), 1);
+        }
+        let mut tail = [0; 0x100];
+        memory.dma_read(MEMORY_END - 0xff, &mut tail).unwrap();
+        assert_eq!(tail, [0; 0x100]);
+    }
+
+    #[test]
+    fn zero_sized_screen_is_an_error() {
+        let result = Ysyx::new(&YsyxConfig {
+            width: 0,
+            headless: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidInput)
+        );
     }
 }
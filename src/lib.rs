@@ -1,3 +1,3 @@
 pub mod bus;
-pub(crate) mod devices;
+pub mod devices;
 pub mod utils;
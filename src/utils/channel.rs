@@ -1,12 +1,25 @@
 use std::{
-    collections::LinkedList,
-    sync::{Arc, Condvar, Mutex},
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, Weak},
+};
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
 };
 
 #[derive(Debug)]
 pub struct Channel<T> {
-    buffer: Arc<Mutex<LinkedList<T>>>,
+    buffer: Arc<Mutex<VecDeque<T>>>,
     condvar: Arc<Condvar>,
+    // None for an unbounded channel
+    capacity: Option<usize>,
+    #[cfg(feature = "async")]
+    wakers: Arc<Mutex<Vec<Waker>>>,
+    // every Sender/Receiver holds a strong reference to its own token
+    senders: Weak<()>,
+    receivers: Weak<()>,
 }
 
 impl<T> Clone for Channel<T> {
@@ -14,25 +27,108 @@ impl<T> Clone for Channel<T> {
         Self {
             buffer: self.buffer.clone(),
             condvar: self.condvar.clone(),
+            capacity: self.capacity,
+            #[cfg(feature = "async")]
+            wakers: self.wakers.clone(),
+            senders: self.senders.clone(),
+            receivers: self.receivers.clone(),
+        }
+    }
+}
+
+impl<T> Channel<T> {
+    fn is_full(&self, buffer: &VecDeque<T>) -> bool {
+        self.capacity
+            .is_some_and(|capacity| buffer.len() >= capacity)
+    }
+
+    // must be called with the buffer locked so a pending RecvFuture can't miss it
+    fn notify(&self) {
+        self.condvar.notify_all();
+        #[cfg(feature = "async")]
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
         }
     }
+
+    pub fn sender_count(&self) -> usize {
+        self.senders.strong_count()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.receivers.strong_count()
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Sender<T> {
     channel: Channel<T>,
+    // only None while being dropped
+    token: Option<Arc<()>>,
 }
 
-#[derive(Debug)]
+/// Receivers may be cloned to share one stream between several consumers;
+/// each item is still delivered to exactly one of them.
+#[derive(Debug, Clone)]
 pub struct Receiver<T> {
     channel: Channel<T>,
+    _token: Arc<()>,
 }
 
 impl<T> Sender<T> {
     pub fn send(&self, t: T) {
         let mut buffer = self.channel.buffer.lock().unwrap();
+        while self.channel.is_full(&buffer) {
+            buffer = self.channel.condvar.wait(buffer).unwrap();
+        }
         buffer.push_back(t);
-        self.channel.condvar.notify_one();
+        self.channel.notify();
+    }
+
+    /// Like `send`, but hands `t` back instead of blocking when a bounded
+    /// channel is at capacity.
+    pub fn try_send(&self, t: T) -> Result<(), T> {
+        let mut buffer = self.channel.buffer.lock().unwrap();
+        if self.channel.is_full(&buffer) {
+            return Err(t);
+        }
+        buffer.push_back(t);
+        self.channel.notify();
+        Ok(())
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // release the token under the lock so a waiting receiver that sees the
+        // notification also sees the updated sender count
+        let _buffer = self.channel.buffer.lock();
+        self.token.take();
+        self.channel.notify();
+    }
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends every item in `items` in order while taking the lock only once
+    /// (a bounded channel still waits for room as it fills up).
+    pub fn send_all(&self, items: &[T]) {
+        let mut buffer = self.channel.buffer.lock().unwrap();
+        for t in items {
+            while self.channel.is_full(&buffer) {
+                self.channel.notify();
+                buffer = self.channel.condvar.wait(buffer).unwrap();
+            }
+            buffer.push_back(t.clone());
+        }
+        self.channel.notify();
     }
 }
 
@@ -45,26 +141,255 @@ impl<T> Receiver<T> {
     pub fn clear(&self) {
         let mut buffer = self.channel.buffer.lock().unwrap();
         buffer.clear();
+        // wake senders blocked on a full bounded channel
+        self.channel.condvar.notify_all();
     }
 
-    pub fn recv(&self) -> T {
+    /// Blocks until an item is available and returns it. Returns `None` only
+    /// once every `Sender` has been dropped and the buffer is drained, so
+    /// `while let Some(t) = receiver.recv()` consumes the whole stream.
+    pub fn recv(&self) -> Option<T> {
         let mut buffer = self.channel.buffer.lock().unwrap();
-        if buffer.is_empty() {
+        while buffer.is_empty() {
+            if self.channel.sender_count() == 0 {
+                return None;
+            }
             buffer = self.channel.condvar.wait(buffer).unwrap();
         }
-        buffer.pop_front().unwrap()
+        let t = buffer.pop_front();
+        if self.channel.capacity.is_some() {
+            self.channel.condvar.notify_all();
+        }
+        t
+    }
+
+    /// Asynchronous counterpart of `recv`, resolving once an item is available
+    /// or the channel is closed and drained.
+    #[cfg(feature = "async")]
+    pub fn recv_async(&self) -> impl Future<Output = Option<T>> + '_ {
+        RecvFuture { receiver: self }
+    }
+
+    pub fn sender_count(&self) -> usize {
+        self.channel.sender_count()
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        self.channel.receiver_count()
+    }
+}
+
+#[cfg(feature = "async")]
+struct RecvFuture<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+#[cfg(feature = "async")]
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let channel = &self.receiver.channel;
+        let mut buffer = channel.buffer.lock().unwrap();
+        if let Some(t) = buffer.pop_front() {
+            if channel.capacity.is_some() {
+                channel.notify();
+            }
+            return Poll::Ready(Some(t));
+        }
+        if channel.sender_count() == 0 {
+            return Poll::Ready(None);
+        }
+        let mut wakers = channel.wakers.lock().unwrap();
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
     }
 }
 
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    new_channel(VecDeque::new(), None)
+}
+
+/// An unbounded channel with room for `capacity` items reserved up front.
+pub fn channel_with_capacity<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    new_channel(VecDeque::with_capacity(capacity), None)
+}
+
+/// A channel holding at most `capacity` items; `send` blocks while it is full.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    new_channel(VecDeque::with_capacity(capacity), Some(capacity))
+}
+
+fn new_channel<T>(buffer: VecDeque<T>, capacity: Option<usize>) -> (Sender<T>, Receiver<T>) {
+    let sender_token = Arc::new(());
+    let receiver_token = Arc::new(());
     let channel: Channel<T> = Channel {
-        buffer: Arc::new(Mutex::new(LinkedList::new())),
+        buffer: Arc::new(Mutex::new(buffer)),
         condvar: Arc::new(Condvar::new()),
+        capacity,
+        #[cfg(feature = "async")]
+        wakers: Arc::new(Mutex::new(Vec::new())),
+        senders: Arc::downgrade(&sender_token),
+        receivers: Arc::downgrade(&receiver_token),
     };
     (
         Sender {
             channel: channel.clone(),
+            token: Some(sender_token),
+        },
+        Receiver {
+            channel,
+            _token: receiver_token,
         },
-        Receiver { channel },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_follow_clones_and_drops() {
+        let (sender, receiver) = channel::<u8>();
+        assert_eq!(sender.sender_count(), 1);
+        assert_eq!(sender.receiver_count(), 1);
+
+        let second = sender.clone();
+        assert_eq!(receiver.sender_count(), 2);
+        drop(second);
+        assert_eq!(receiver.sender_count(), 1);
+
+        drop(sender);
+        assert_eq!(receiver.sender_count(), 0);
+        assert_eq!(receiver.receiver_count(), 1);
+    }
+
+    #[test]
+    fn try_send_hands_back_when_full() {
+        let (sender, receiver) = bounded(2);
+        assert_eq!(sender.try_send(1), Ok(()));
+        assert_eq!(sender.try_send(2), Ok(()));
+        assert_eq!(sender.try_send(3), Err(3));
+
+        assert_eq!(receiver.recv(), Some(1));
+        assert_eq!(sender.try_send(4), Ok(()));
+    }
+
+    #[cfg(feature = "async")]
+    fn block_on<F: Future>(future: F) -> F::Output {
+        use std::{
+            sync::Arc,
+            task::Wake,
+            thread::{self, Thread},
+        };
+
+        struct Unpark(Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn recv_async_round_trip() {
+        let (sender, receiver) = channel();
+        let producer = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            sender.send(7u8);
+        });
+        assert_eq!(block_on(receiver.recv_async()), Some(7));
+        producer.join().unwrap();
+        assert_eq!(block_on(receiver.recv_async()), None);
+    }
+
+    #[test]
+    fn send_all_keeps_order() {
+        let (sender, receiver) = channel();
+        sender.send_all(b"hello\n");
+        drop(sender);
+        let mut received = Vec::new();
+        while let Some(byte) = receiver.recv() {
+            received.push(byte);
+        }
+        assert_eq!(received, b"hello\n");
+    }
+
+    #[test]
+    fn consumers_split_without_duplication() {
+        let (sender, receiver) = channel();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let receiver = receiver.clone();
+                std::thread::spawn(move || {
+                    let mut received = Vec::new();
+                    while let Some(t) = receiver.recv() {
+                        received.push(t);
+                    }
+                    received
+                })
+            })
+            .collect();
+        drop(receiver);
+
+        for t in 0..1000u32 {
+            sender.send(t);
+        }
+        drop(sender);
+
+        let mut received: Vec<u32> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        received.sort_unstable();
+        assert_eq!(received, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn with_capacity_reserves_without_bounding() {
+        let (sender, receiver) = channel_with_capacity(64);
+        assert!(sender.channel.buffer.lock().unwrap().capacity() >= 64);
+
+        for t in 0..100 {
+            assert_eq!(sender.try_send(t), Ok(()));
+        }
+        for t in 0..100 {
+            assert_eq!(receiver.recv(), Some(t));
+        }
+        assert!(!receiver.avaliable());
+    }
+
+    #[test]
+    fn recv_drains_then_returns_none() {
+        let (sender, receiver) = channel();
+        sender.send(1);
+        sender.send(2);
+        drop(sender);
+        assert_eq!(receiver.recv(), Some(1));
+        assert_eq!(receiver.recv(), Some(2));
+        assert_eq!(receiver.recv(), None);
+    }
+
+    #[test]
+    fn recv_wakes_when_last_sender_drops() {
+        let (sender, receiver) = channel::<u8>();
+        let waiter = std::thread::spawn(move || receiver.recv());
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        drop(sender);
+        assert_eq!(waiter.join().unwrap(), None);
+    }
+}
@@ -1,4 +1,7 @@
-pub(crate) mod channel;
+pub mod channel;
+pub mod serial;
+#[cfg(all(target_os = "linux", feature = "tap"))]
+pub mod tap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Size {
@@ -10,6 +13,80 @@ pub enum Size {
     _4,
     // double word
     _8,
+    // quad word, only reachable through the 128-bit bus path
+    _16,
+}
+
+impl Size {
+    /// Every access width, narrowest first.
+    pub fn all() -> impl Iterator<Item = Size> {
+        [Size::_1, Size::_2, Size::_4, Size::_8, Size::_16].into_iter()
+    }
+
+    /// Width of the access in bytes.
+    pub fn bytes(self) -> usize {
+        match self {
+            Size::_1 => 1,
+            Size::_2 => 2,
+            Size::_4 => 4,
+            Size::_8 => 8,
+            Size::_16 => 16,
+        }
+    }
+
+    /// Bitmask covering the low `bytes()` bytes, for `data & size.mask()`.
+    /// Wide enough for `_16`; narrow it with `as u64` on the 64-bit path.
+    pub fn mask(self) -> u128 {
+        match self {
+            Size::_1 => 0xFF,
+            Size::_2 => 0xFFFF,
+            Size::_4 => 0xFFFF_FFFF,
+            Size::_8 => 0xFFFF_FFFF_FFFF_FFFF,
+            Size::_16 => u128::MAX,
+        }
+    }
+
+    /// Treats the low `bytes() * 8` bits of `value` as signed and extends
+    /// them to 64 bits, as `lb`/`lh`/`lw` do.
+    pub fn sign_extend(self, value: u64) -> i64 {
+        match self {
+            Size::_1 => value as i8 as i64,
+            Size::_2 => value as i16 as i64,
+            Size::_4 => value as i32 as i64,
+            Size::_8 | Size::_16 => value as i64,
+        }
+    }
+
+    /// Whether `address` is a multiple of the access width.
+    pub fn is_aligned(self, address: u32) -> bool {
+        address.is_multiple_of(self.bytes() as u32)
+    }
+
+    /// Inverse of `bytes`, `None` for widths that aren't a supported access.
+    pub fn from_bytes(bytes: usize) -> Option<Size> {
+        match bytes {
+            1 => Some(Size::_1),
+            2 => Some(Size::_2),
+            4 => Some(Size::_4),
+            8 => Some(Size::_8),
+            16 => Some(Size::_16),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeError {
+    // the byte count isn't a supported access width
+    UnsupportedWidth(usize),
+}
+
+impl TryFrom<usize> for Size {
+    type Error = SizeError;
+
+    fn try_from(bytes: usize) -> Result<Self, Self::Error> {
+        Size::from_bytes(bytes).ok_or(SizeError::UnsupportedWidth(bytes))
+    }
 }
 
 pub(crate) fn u32_to_u8(arr: &mut [u32]) -> &mut [u8] {
@@ -18,7 +95,145 @@ pub(crate) fn u32_to_u8(arr: &mut [u32]) -> &mut [u8] {
     unsafe { std::slice::from_raw_parts_mut(ptr, len) }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Exception {
+    // catch-all for failures with no better classification
     BusException,
+    // the faulting access, for mtval
+    InstructionAddressMisaligned { address: u32, size: Size },
+    InstructionAccessFault { address: u32, size: Size },
+    LoadAddressMisaligned { address: u32, size: Size },
+    LoadAccessFault { address: u32, size: Size },
+    StoreAddressMisaligned { address: u32, size: Size },
+    StoreAccessFault { address: u32, size: Size },
+}
+
+impl Exception {
+    /// RISC-V `mcause` exception code. `BusException` carries no direction,
+    /// so it is reported as a load access fault.
+    pub fn cause_code(&self) -> u32 {
+        match self {
+            Exception::InstructionAddressMisaligned { .. } => 0,
+            Exception::InstructionAccessFault { .. } => 1,
+            Exception::LoadAddressMisaligned { .. } => 4,
+            Exception::BusException | Exception::LoadAccessFault { .. } => 5,
+            Exception::StoreAddressMisaligned { .. } => 6,
+            Exception::StoreAccessFault { .. } => 7,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_per_size() {
+        let bytes: Vec<usize> = Size::all().map(Size::bytes).collect();
+        assert_eq!(bytes, [1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn from_bytes_round_trips() {
+        for size in Size::all() {
+            assert_eq!(Size::from_bytes(size.bytes()), Some(size));
+        }
+        assert_eq!(Size::from_bytes(0), None);
+        assert_eq!(Size::from_bytes(3), None);
+        assert_eq!(Size::from_bytes(32), None);
+    }
+
+    #[test]
+    fn try_from_mirrors_from_bytes() {
+        for size in Size::all() {
+            assert_eq!(Size::try_from(size.bytes()), Ok(size));
+        }
+        assert_eq!(Size::try_from(3), Err(SizeError::UnsupportedWidth(3)));
+        assert_eq!(Size::try_from(32), Err(SizeError::UnsupportedWidth(32)));
+    }
+
+    #[test]
+    fn mask_per_size() {
+        assert_eq!(Size::_1.mask(), 0xFF);
+        assert_eq!(Size::_2.mask(), 0xFFFF);
+        assert_eq!(Size::_4.mask(), 0xFFFF_FFFF);
+        assert_eq!(Size::_8.mask(), u64::MAX as u128);
+        assert_eq!(Size::_16.mask(), u128::MAX);
+    }
+
+    #[test]
+    fn alignment_per_size() {
+        for address in [
+            0x8000_0000,
+            0x8000_0001,
+            0x8000_0002,
+            0x8000_0004,
+            0x8000_0008,
+            0x8000_000c,
+        ] {
+            assert!(Size::_1.is_aligned(address));
+        }
+        assert!(Size::_2.is_aligned(0x8000_0002));
+        assert!(!Size::_2.is_aligned(0x8000_0001));
+        assert!(Size::_4.is_aligned(0x8000_000c));
+        assert!(!Size::_4.is_aligned(0x8000_0002));
+        assert!(Size::_8.is_aligned(0x8000_0008));
+        assert!(!Size::_8.is_aligned(0x8000_0004));
+        assert!(Size::_16.is_aligned(0x8000_0010));
+        assert!(!Size::_16.is_aligned(0x8000_0008));
+    }
+
+    #[test]
+    fn sign_extend_negative_values() {
+        assert_eq!(Size::_1.sign_extend(0x80), -128);
+        assert_eq!(Size::_1.sign_extend(0x7f), 127);
+        assert_eq!(Size::_2.sign_extend(0xfffe), -2);
+        assert_eq!(Size::_4.sign_extend(0xffff_ffff), -1);
+        // bits above the access width are ignored
+        assert_eq!(Size::_1.sign_extend(0x1234_56ff), -1);
+        assert_eq!(Size::_8.sign_extend(u64::MAX), -1);
+    }
+
+    #[test]
+    fn all_yields_every_width() {
+        assert_eq!(
+            Size::all().collect::<Vec<_>>(),
+            [Size::_1, Size::_2, Size::_4, Size::_8, Size::_16]
+        );
+    }
+
+    #[test]
+    fn cause_codes_match_the_spec() {
+        let (address, size) = (0x8000_0000, Size::_4);
+        let cases = [
+            (Exception::InstructionAddressMisaligned { address, size }, 0),
+            (Exception::InstructionAccessFault { address, size }, 1),
+            (Exception::LoadAddressMisaligned { address, size }, 4),
+            (Exception::LoadAccessFault { address, size }, 5),
+            (Exception::StoreAddressMisaligned { address, size }, 6),
+            (Exception::StoreAccessFault { address, size }, 7),
+            (Exception::BusException, 5),
+        ];
+        for (exception, code) in cases {
+            assert_eq!(exception.cause_code(), code, "{exception:?}");
+        }
+    }
+
+    #[test]
+    fn exceptions_compare() {
+        let fault = Exception::LoadAccessFault {
+            address: 0x10,
+            size: Size::_4,
+        };
+        let copy = fault;
+        assert_eq!(fault, copy);
+        assert_ne!(
+            fault,
+            Exception::LoadAccessFault {
+                address: 0x14,
+                size: Size::_4,
+            }
+        );
+        assert_ne!(fault, Exception::BusException);
+    }
 }
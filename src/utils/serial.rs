@@ -0,0 +1,275 @@
+#[cfg(all(unix, feature = "pty"))]
+use std::{
+    ffi::CStr,
+    fs::{File, OpenOptions},
+    os::fd::FromRawFd,
+    path::PathBuf,
+};
+use std::{
+    io::{self, Read, Write},
+    thread::{self, JoinHandle},
+};
+#[cfg(feature = "tcp")]
+use std::{
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use super::channel::{Receiver, Sender};
+
+/// Spawns a thread forwarding every byte read from `reader` into `sender`.
+///
+/// The thread ends at EOF or on the first read error, dropping its sender so
+/// the receiving side observes the stream closing. It is not otherwise
+/// interruptible: while blocked in `read` it holds on until input arrives.
+pub fn forward_input<R: Read + Send + 'static>(
+    mut reader: R,
+    sender: Sender<u8>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0; 256];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => sender.send_all(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// Spawns a thread draining `receiver` into `writer`, flushing at every
+/// newline and whenever the channel runs dry so prompts show up promptly.
+///
+/// The thread ends once every sender is gone and the channel is drained, or
+/// on the first write error.
+pub fn forward_output<W: Write + Send + 'static>(
+    receiver: Receiver<u8>,
+    mut writer: W,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while let Some(byte) = receiver.recv() {
+            if writer.write_all(&[byte]).is_err() {
+                break;
+            }
+            if (byte == b'\n' || !receiver.avaliable()) && writer.flush().is_err() {
+                break;
+            }
+        }
+        let _ = writer.flush();
+    })
+}
+
+/// Bridges connections accepted on `listener` to a UART, like QEMU's
+/// `-serial tcp:` mode. One client is served at a time; once it disconnects
+/// the next one is accepted. Output produced while no client is connected is
+/// discarded.
+///
+/// The returned thread keeps accepting until the listener fails.
+#[cfg(feature = "tcp")]
+pub fn serve_tcp(
+    listener: TcpListener,
+    sender: Sender<u8>,
+    receiver: Receiver<u8>,
+) -> JoinHandle<()> {
+    let client: Arc<Mutex<Option<TcpStream>>> = Arc::new(Mutex::new(None));
+
+    let output = client.clone();
+    thread::spawn(move || {
+        while let Some(byte) = receiver.recv() {
+            let mut output = output.lock().unwrap();
+            if let Some(stream) = output.as_mut()
+                && stream.write_all(&[byte]).is_err()
+            {
+                *output = None;
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                break;
+            };
+            let Ok(writer) = stream.try_clone() else {
+                continue;
+            };
+            *client.lock().unwrap() = Some(writer);
+            let mut buffer = [0; 256];
+            loop {
+                match stream.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => sender.send_all(&buffer[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => break,
+                }
+            }
+            *client.lock().unwrap() = None;
+        }
+    })
+}
+
+/// Allocates a pseudo-terminal bridged to a UART and returns the path of its
+/// slave side, for `screen` or `minicom` to attach to.
+///
+/// The input thread keeps the slave open itself so the master does not report
+/// EOF between clients, which also means it runs for the rest of the process.
+/// The output thread ends once the UART side of the channel closes.
+#[cfg(all(unix, feature = "pty"))]
+pub fn serve_pty(sender: Sender<u8>, receiver: Receiver<u8>) -> io::Result<PathBuf> {
+    // SAFETY: plain libc calls on a descriptor owned here, and a buffer sized
+    // for ptsname_r
+    let (master, path) = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut name = [0 as libc::c_char; 128];
+        if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned());
+
+        // raw mode, bytes pass through untouched
+        let mut termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (master, path)
+    };
+
+    let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+    forward_input(
+        HoldOpen {
+            reader: master.try_clone()?,
+            _slave: slave,
+        },
+        sender,
+    );
+    forward_output(receiver, master);
+    Ok(path)
+}
+
+#[cfg(all(unix, feature = "pty"))]
+struct HoldOpen {
+    reader: File,
+    _slave: File,
+}
+
+#[cfg(all(unix, feature = "pty"))]
+impl Read for HoldOpen {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::channel::channel;
+
+    #[test]
+    fn input_closes_at_eof() {
+        let (sender, receiver) = channel();
+        let thread = forward_input(io::Cursor::new(b"ls\n".to_vec()), sender);
+        let mut received = Vec::new();
+        while let Some(byte) = receiver.recv() {
+            received.push(byte);
+        }
+        assert_eq!(received, b"ls\n");
+        thread.join().unwrap();
+    }
+
+    // a `Write` the test can still inspect after handing it to a thread
+    #[derive(Clone, Default)]
+    struct Capture(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uart_output_reaches_the_writer() {
+        use crate::{
+            devices::{
+                Device,
+                uart::{INTERRUPT_ID, UART_START, Uart, UartConfig},
+            },
+            utils::Size,
+        };
+
+        let (mut uart, _sender, receiver) = Uart::new(&UartConfig::new(UART_START, INTERRUPT_ID));
+        let capture = Capture::default();
+        let thread = forward_output(receiver, capture.clone());
+        // FIFO on, so all three bytes fit before any is sent
+        uart.write(UART_START + 2, Size::_1, 1).unwrap();
+        for byte in b"ok\n" {
+            uart.write(UART_START, Size::_1, *byte as u64).unwrap();
+        }
+        // dropping the UART flushes it and closes the channel
+        drop(uart);
+        thread.join().unwrap();
+        assert_eq!(*capture.0.lock().unwrap(), b"ok\n");
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn tcp_moves_bytes_both_ways() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (to_uart, from_host) = channel();
+        let (to_host, from_uart) = channel();
+        serve_tcp(listener, to_uart, from_uart);
+
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"hi").unwrap();
+        assert_eq!(from_host.recv(), Some(b'h'));
+        assert_eq!(from_host.recv(), Some(b'i'));
+
+        // the bridge has read from the client, so it is attached by now
+        to_host.send_all(b"ok");
+        let mut reply = [0; 2];
+        client.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"ok");
+    }
+
+    #[cfg(all(unix, feature = "pty"))]
+    #[test]
+    fn pty_round_trip() {
+        let (to_uart, from_host) = channel();
+        let (to_host, from_uart) = channel();
+        let path = serve_pty(to_uart, from_uart).unwrap();
+        let mut slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        slave.write_all(b"at\r").unwrap();
+        assert_eq!(from_host.recv(), Some(b'a'));
+        assert_eq!(from_host.recv(), Some(b't'));
+        // raw mode, so no CR to LF translation
+        assert_eq!(from_host.recv(), Some(b'\r'));
+
+        to_host.send_all(b"OK");
+        let mut reply = [0; 2];
+        slave.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"OK");
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    ffi::CStr,
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    thread,
+};
+
+use super::channel::{Receiver, Sender};
+
+/// Attaches a virtio-net device's frame channels to a Linux tap interface,
+/// creating it if needed, and returns the interface's name. An empty `name`
+/// lets the kernel pick one. Usually needs `CAP_NET_ADMIN`, or a persistent
+/// tap owned by the user (`ip tuntap add mode tap user ...`).
+///
+/// Frames are forwarded until the tap fails; the output side also stops once
+/// the device's sender is gone.
+pub fn serve_tap(
+    name: &str,
+    sender: Sender<Vec<u8>>,
+    receiver: Receiver<Vec<u8>>,
+) -> io::Result<String> {
+    let tap = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/net/tun")?;
+    // SAFETY: ifreq is plain data, and the ioctl only touches the request
+    let name = unsafe {
+        let mut request: libc::ifreq = std::mem::zeroed();
+        if name.len() >= request.ifr_name.len() {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        for (dst, &src) in request.ifr_name.iter_mut().zip(name.as_bytes()) {
+            *dst = src as libc::c_char;
+        }
+        request.ifr_ifru.ifru_flags = (libc::IFF_TAP | libc::IFF_NO_PI) as libc::c_short;
+        if libc::ioctl(tap.as_raw_fd(), libc::TUNSETIFF, &mut request) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CStr::from_ptr(request.ifr_name.as_ptr())
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let mut reader = tap.try_clone()?;
+    thread::spawn(move || {
+        // each read returns exactly one frame
+        let mut buffer = vec![0; 65536];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(n) => sender.send(buffer[..n].to_vec()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    });
+    let mut writer = tap;
+    thread::spawn(move || {
+        while let Some(frame) = receiver.recv() {
+            if writer.write_all(&frame).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(name)
+}